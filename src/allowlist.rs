@@ -0,0 +1,540 @@
+//! Restricting a share to the node ids of specific receivers.
+//!
+//! The provider of iroh-bytes takes an incoming connection before the node id
+//! of the peer is known, so it can not refuse anyone. Restricted shares accept
+//! connections themselves instead, check the node id, and forward the streams
+//! of allowed peers to a provider on a loopback address. The provider only
+//! serves connections the gate made for an allowed peer.
+//!
+//! Forwarding also shows the requests of each receiver, which shares with a
+//! download limit use to tell downloads from previews. The provider events
+//! then tell which files each receiver was sent.
+use std::{
+    collections::{BTreeSet, HashMap},
+    net::{Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+};
+
+use bao_tree::{ChunkNum, ChunkRanges};
+use futures::future::BoxFuture;
+use iroh_bytes::{
+    protocol::{GetRequest, Request, ALPN},
+    provider::{handle_connection, Event, EventSender},
+    store::Map,
+    Hash,
+};
+use iroh_net::{
+    key::{PublicKey, SecretKey},
+    magic_endpoint::get_remote_node_id,
+    tls,
+};
+use tokio::task::JoinHandle;
+use tokio_util::task::LocalPoolHandle;
+use tracing::{debug, info};
+
+/// Error code of connections closed because the peer is not allowed.
+const REFUSED: u32 = 1;
+
+/// Largest request forwarded to the provider.
+const MAX_REQUEST_SIZE: usize = 1024 * 1024;
+
+/// Node ids allowed to fetch a share.
+///
+/// Clones share the same list, so a share can be restricted further while it
+/// runs. Changes apply to new connections.
+#[derive(Debug, Clone, Default)]
+pub struct Allowlist(Arc<Mutex<Option<BTreeSet<PublicKey>>>>);
+
+impl Allowlist {
+    /// Anyone with the ticket may fetch the share.
+    pub fn anyone() -> Self {
+        Self::default()
+    }
+
+    /// Only the given node ids may fetch the share.
+    pub fn only(node_ids: impl IntoIterator<Item = PublicKey>) -> Self {
+        Self(Arc::new(Mutex::new(Some(node_ids.into_iter().collect()))))
+    }
+
+    /// Allow `node_id`. A share that was open to anyone is then restricted to it.
+    pub fn allow(&self, node_id: PublicKey) {
+        self.0
+            .lock()
+            .unwrap()
+            .get_or_insert_with(BTreeSet::new)
+            .insert(node_id);
+    }
+
+    /// No longer allow `node_id`. The share stays restricted, even if no node
+    /// id is left.
+    pub fn revoke(&self, node_id: &PublicKey) {
+        if let Some(node_ids) = self.0.lock().unwrap().as_mut() {
+            node_ids.remove(node_id);
+        }
+    }
+
+    /// Whether only some node ids may fetch the share.
+    pub fn is_restricted(&self) -> bool {
+        self.0.lock().unwrap().is_some()
+    }
+
+    pub fn allows(&self, node_id: &PublicKey) -> bool {
+        match self.0.lock().unwrap().as_ref() {
+            Some(node_ids) => node_ids.contains(node_id),
+            None => true,
+        }
+    }
+
+    /// The allowed node ids, empty if anyone may fetch the share.
+    pub fn node_ids(&self) -> Vec<PublicKey> {
+        self.0.lock().unwrap().iter().flatten().copied().collect()
+    }
+}
+
+/// Counts the receivers that got a whole collection.
+///
+/// The provider reports every blob it sent. A receiver counts once it was
+/// sent every file of the collection, over any number of requests and
+/// connections, so downloads that were resumed or fetched file by file count
+/// too. Only blobs the request asked data of are taken: to learn the sizes of
+/// the files, a preview asks for the last chunk of each, which the provider
+/// reports as sent like any other blob.
+#[derive(Debug, Clone)]
+pub(crate) struct Downloads {
+    /// Hash of the hash sequence of the collection.
+    root: Hash,
+    /// Hashes of the files, in collection order.
+    files: Arc<Vec<Hash>>,
+    /// The files each receiver was sent so far, by index.
+    received: Arc<Mutex<HashMap<PublicKey, BTreeSet<usize>>>>,
+    /// Gets the node id of each receiver once it got the whole collection.
+    done: flume::Sender<PublicKey>,
+}
+
+impl Downloads {
+    pub(crate) fn new(root: Hash, files: Vec<Hash>, done: flume::Sender<PublicKey>) -> Self {
+        Self {
+            root,
+            files: Arc::new(files),
+            received: Default::default(),
+            done,
+        }
+    }
+
+    /// Indices of the files with `hash` whose data `request` asked for.
+    fn requested(&self, request: &GetRequest, hash: Hash) -> Vec<usize> {
+        self.files
+            .iter()
+            .enumerate()
+            .filter(|(_, file)| **file == hash)
+            .filter(|(i, _)| {
+                // a request for the hash sequence has the ranges of the hash
+                // sequence itself and of the metadata before the files
+                let ranges = if request.hash == self.root {
+                    request.ranges.iter().nth(i + 2)
+                } else if request.hash == hash {
+                    request.ranges.iter().next()
+                } else {
+                    None
+                };
+                ranges.is_some_and(|ranges| asks_for_data(&ranges.to_chunk_ranges()))
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Note that `node_id` was sent the files with the indices `files`.
+    fn add(&self, node_id: PublicKey, files: Vec<usize>) {
+        if files.is_empty() {
+            return;
+        }
+        let mut received = self.received.lock().unwrap();
+        let received = received.entry(node_id).or_default();
+        let before = received.len();
+        received.extend(files);
+        if before < self.files.len() && received.len() == self.files.len() {
+            info!("{} got the whole collection", node_id);
+            self.done.send(node_id).ok();
+        }
+    }
+}
+
+/// Whether `ranges` ask for data, rather than only for the size of a blob.
+///
+/// The size is in the last chunk, which is what
+/// [`get_hash_seq_and_sizes`](iroh_bytes::get::request::get_hash_seq_and_sizes)
+/// asks for.
+fn asks_for_data(ranges: &ChunkRanges) -> bool {
+    !ranges.is_empty() && *ranges != ChunkRanges::from(ChunkNum(u64::MAX)..)
+}
+
+/// The requests of one receiver, over one connection to the provider.
+#[derive(Debug, Clone)]
+struct Receiver {
+    node_id: PublicKey,
+    downloads: Downloads,
+    /// Requests the provider is still working on, by stream id.
+    requests: Arc<Mutex<HashMap<u64, GetRequest>>>,
+}
+
+impl Receiver {
+    fn new(node_id: PublicKey, downloads: Downloads) -> Self {
+        Self {
+            node_id,
+            downloads,
+            requests: Default::default(),
+        }
+    }
+
+    /// Note the encoded request on stream `id`, before it is forwarded.
+    fn request(&self, id: u64, request: &[u8]) {
+        if let Ok(Request::Get(request)) = postcard::from_bytes(request) {
+            self.requests.lock().unwrap().insert(id, request);
+        }
+    }
+
+    /// The provider sent the blob `hash` of the hash sequence requested on
+    /// stream `id`.
+    fn sent(&self, id: u64, hash: Hash) {
+        let files = match self.requests.lock().unwrap().get(&id) {
+            Some(request) => self.downloads.requested(request, hash),
+            None => return,
+        };
+        self.downloads.add(self.node_id, files);
+    }
+
+    /// The provider is done with the request on stream `id`.
+    fn finished(&self, id: u64, completed: bool) {
+        let Some(request) = self.requests.lock().unwrap().remove(&id) else {
+            return;
+        };
+        // a single blob has no events of its own
+        if completed && request.hash != self.downloads.root {
+            let files = self.downloads.requested(&request, request.hash);
+            self.downloads.add(self.node_id, files);
+        }
+    }
+}
+
+/// Provider events of one connection through the gate.
+#[derive(Debug, Clone)]
+struct ReceiverEvents<E> {
+    events: E,
+    receiver: Receiver,
+}
+
+impl<E: EventSender> EventSender for ReceiverEvents<E> {
+    fn send(&self, event: Event) -> BoxFuture<()> {
+        match &event {
+            Event::TransferBlobCompleted {
+                request_id, hash, ..
+            } => self.receiver.sent(*request_id, *hash),
+            Event::TransferCompleted { request_id, .. } => {
+                self.receiver.finished(*request_id, true)
+            }
+            Event::TransferAborted { request_id, .. } => self.receiver.finished(*request_id, false),
+            _ => {}
+        }
+        self.events.send(event)
+    }
+}
+
+/// Lets allowed peers through to a provider on a loopback endpoint.
+#[derive(Debug)]
+pub(crate) struct Gate {
+    /// Connects to the provider on behalf of all allowed peers.
+    client: quinn::Endpoint,
+    client_config: quinn::ClientConfig,
+    provider: quinn::Endpoint,
+    provider_addr: SocketAddr,
+    /// The receiver behind each connection to the provider, by the server
+    /// name the gate connected with.
+    receivers: Arc<Mutex<HashMap<String, Receiver>>>,
+    downloads: Downloads,
+    task: JoinHandle<()>,
+}
+
+impl Gate {
+    /// Start a provider serving `db`, only reachable through the gate.
+    pub(crate) fn start<D, E>(
+        db: D,
+        events: E,
+        rt: LocalPoolHandle,
+        downloads: Downloads,
+    ) -> anyhow::Result<Self>
+    where
+        D: Map,
+        E: EventSender,
+    {
+        let loopback = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+        let secret_key = SecretKey::generate();
+        let server_config = tls::make_server_config(&secret_key, vec![ALPN.to_vec()], false)?;
+        let provider = quinn::Endpoint::server(
+            quinn::ServerConfig::with_crypto(Arc::new(server_config)),
+            loopback,
+        )?;
+        let provider_addr = provider.local_addr()?;
+        let client = quinn::Endpoint::client(loopback)?;
+        let client_addr = client.local_addr()?;
+        let client_config = tls::make_client_config(
+            &secret_key,
+            Some(secret_key.public()),
+            vec![ALPN.to_vec()],
+            false,
+        )?;
+        let client_config = quinn::ClientConfig::new(Arc::new(client_config));
+        let receivers: Arc<Mutex<HashMap<String, Receiver>>> = Default::default();
+        let task = tokio::spawn({
+            let provider = provider.clone();
+            let receivers = receivers.clone();
+            async move {
+                while let Some(connecting) = provider.accept().await {
+                    let db = db.clone();
+                    let events = events.clone();
+                    let rt = rt.clone();
+                    let receivers = receivers.clone();
+                    tokio::spawn(async move {
+                        let Some((connecting, receiver)) =
+                            identify(connecting, client_addr, &receivers).await
+                        else {
+                            // dropping the connection refuses it
+                            info!("refused a connection that did not come through the gate");
+                            return;
+                        };
+                        let events = ReceiverEvents { events, receiver };
+                        handle_connection(connecting, db, events, rt).await
+                    });
+                }
+            }
+        });
+        Ok(Self {
+            client,
+            client_config,
+            provider,
+            provider_addr,
+            receivers,
+            downloads,
+            task,
+        })
+    }
+
+    /// Accept `connecting`, and serve it if the peer is on `allowlist`.
+    pub(crate) async fn handle(
+        &self,
+        connecting: quinn::Connecting,
+        allowlist: Allowlist,
+    ) -> anyhow::Result<()> {
+        let connection = connecting.await?;
+        let node_id = get_remote_node_id(&connection)?;
+        if !allowlist.allows(&node_id) {
+            info!("refused {}, it is not allowed", node_id);
+            connection.close(REFUSED.into(), b"not allowed");
+            return Ok(());
+        }
+        debug!("{} is allowed", node_id);
+        // a name nobody else can guess, which the provider checks
+        let name = format!("receiver{:016x}", rand::random::<u64>());
+        let receiver = Receiver::new(node_id, self.downloads.clone());
+        self.receivers
+            .lock()
+            .unwrap()
+            .insert(name.clone(), receiver.clone());
+        let res = async {
+            let inner = self
+                .client
+                .connect_with(self.client_config.clone(), self.provider_addr, &name)?
+                .await?;
+            loop {
+                let (send, recv) = tokio::select! {
+                    res = connection.accept_bi() => match res {
+                        Ok(streams) => streams,
+                        Err(_) => break,
+                    },
+                    _ = inner.closed() => break,
+                };
+                let (inner_send, inner_recv) = inner.open_bi().await?;
+                tokio::spawn(forward_request(recv, inner_send, receiver.clone()));
+                tokio::spawn(forward(inner_recv, send));
+            }
+            inner.close(0u32.into(), b"peer done");
+            anyhow::Ok(())
+        };
+        let res = res.await;
+        self.receivers.lock().unwrap().remove(&name);
+        res
+    }
+
+    /// Close the provider and all connections going through the gate.
+    pub(crate) fn close(&self) {
+        self.client.close(0u32.into(), b"share stopped");
+        self.provider.close(0u32.into(), b"share stopped");
+        self.task.abort();
+    }
+}
+
+/// The receiver behind a connection to the provider of the gate.
+///
+/// `None` unless the connection comes from the client endpoint of the gate,
+/// with a server name the gate registered for an allowed peer. Anything else
+/// on this machine could otherwise reach the provider directly.
+async fn identify(
+    mut connecting: quinn::Connecting,
+    client_addr: SocketAddr,
+    receivers: &Mutex<HashMap<String, Receiver>>,
+) -> Option<(quinn::Connecting, Receiver)> {
+    if connecting.remote_address() != client_addr {
+        return None;
+    }
+    let data = connecting.handshake_data().await.ok()?;
+    let name = data
+        .downcast::<quinn::crypto::rustls::HandshakeData>()
+        .ok()?
+        .server_name?;
+    let receiver = receivers.lock().unwrap().get(&name).cloned()?;
+    Some((connecting, receiver))
+}
+
+/// Pass the request of `receiver` on to the provider.
+async fn forward_request(
+    mut from: quinn::RecvStream,
+    mut to: quinn::SendStream,
+    receiver: Receiver,
+) {
+    let res = async {
+        let request = from.read_to_end(MAX_REQUEST_SIZE).await?;
+        receiver.request(to.id().index(), &request);
+        to.write_all(&request).await?;
+        to.finish().await?;
+        anyhow::Ok(())
+    };
+    if let Err(err) = res.await {
+        debug!("forwarding request failed: {:?}", err);
+    }
+}
+
+/// Copy a stream until it ends.
+async fn forward(mut from: quinn::RecvStream, mut to: quinn::SendStream) {
+    let res = async {
+        tokio::io::copy(&mut from, &mut to).await?;
+        to.finish().await?;
+        anyhow::Ok(())
+    };
+    if let Err(err) = res.await {
+        debug!("forwarding failed: {:?}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh_bytes::protocol::RangeSpecSeq;
+
+    use super::*;
+
+    fn encode(hash: Hash, ranges: RangeSpecSeq) -> Vec<u8> {
+        postcard::to_stdvec(&Request::Get(GetRequest::new(hash, ranges))).unwrap()
+    }
+
+    #[test]
+    fn allow_and_revoke() {
+        let [a, b] = [
+            SecretKey::generate().public(),
+            SecretKey::generate().public(),
+        ];
+        let allowlist = Allowlist::anyone();
+        assert!(!allowlist.is_restricted());
+        assert!(allowlist.allows(&a));
+        assert!(allowlist.node_ids().is_empty());
+
+        // allowing a node id restricts the share, also for its clones
+        let clone = allowlist.clone();
+        allowlist.allow(a);
+        assert!(clone.is_restricted());
+        assert!(clone.allows(&a));
+        assert!(!clone.allows(&b));
+        assert_eq!(clone.node_ids(), [a]);
+
+        // revoking the last node id keeps the share restricted
+        allowlist.revoke(&a);
+        assert!(allowlist.is_restricted());
+        assert!(!allowlist.allows(&a));
+        assert!(allowlist.node_ids().is_empty());
+
+        let allowlist = Allowlist::only([a, b]);
+        assert!(allowlist.allows(&a) && allowlist.allows(&b));
+        assert!(!Allowlist::only([]).allows(&a));
+    }
+
+    #[test]
+    fn receivers_count_once_they_were_sent_every_file() {
+        let root = Hash::new(b"hash seq of a.txt and b.txt");
+        let meta = Hash::new(b"metadata");
+        let (a, b) = (Hash::new(b"a.txt"), Hash::new(b"b.txt"));
+        let (done_send, done_recv) = flume::unbounded();
+        let downloads = Downloads::new(root, vec![a, b], done_send);
+
+        // the requests of `get`: the sizes, the hash sequence and the
+        // metadata on their own, and then the files
+        let node_id = SecretKey::generate().public();
+        let receiver = Receiver::new(node_id, downloads.clone());
+        let sizes = RangeSpecSeq::from_ranges_infinite([
+            ChunkRanges::all(),
+            ChunkRanges::from(ChunkNum(u64::MAX)..),
+        ]);
+        receiver.request(0, &encode(root, sizes));
+        for hash in [meta, a, b] {
+            receiver.sent(0, hash);
+        }
+        receiver.finished(0, true);
+        for (id, hash) in [(4, root), (8, meta)] {
+            receiver.request(id, &encode(hash, RangeSpecSeq::all()));
+            receiver.finished(id, true);
+        }
+        assert!(done_recv.try_recv().is_err());
+        let files = RangeSpecSeq::from_ranges([
+            ChunkRanges::empty(),
+            ChunkRanges::empty(),
+            ChunkRanges::all(),
+            ChunkRanges::all(),
+        ]);
+        receiver.request(12, &encode(root, files.clone()));
+        receiver.sent(12, a);
+        assert!(done_recv.try_recv().is_err());
+        receiver.sent(12, b);
+        assert_eq!(done_recv.try_recv().unwrap(), node_id);
+        receiver.finished(12, true);
+
+        // and only once
+        receiver.request(16, &encode(root, files));
+        receiver.sent(16, a);
+        receiver.sent(16, b);
+        assert!(done_recv.try_recv().is_err());
+
+        // a resumed download only asks for what is missing, over a new
+        // connection
+        let node_id = SecretKey::generate().public();
+        let receiver = Receiver::new(node_id, downloads.clone());
+        receiver.request(0, &encode(root, RangeSpecSeq::all()));
+        receiver.sent(0, a);
+        receiver.finished(0, false);
+        let receiver = Receiver::new(node_id, downloads.clone());
+        let rest = RangeSpecSeq::from_ranges([
+            ChunkRanges::empty(),
+            ChunkRanges::empty(),
+            ChunkRanges::empty(),
+            ChunkRanges::from(ChunkNum(3)..),
+        ]);
+        receiver.request(0, &encode(root, rest));
+        receiver.sent(0, b);
+        assert_eq!(done_recv.try_recv().unwrap(), node_id);
+
+        // selected files are fetched one by one
+        let node_id = SecretKey::generate().public();
+        let receiver = Receiver::new(node_id, downloads);
+        receiver.request(0, &encode(a, RangeSpecSeq::all()));
+        receiver.finished(0, true);
+        assert!(done_recv.try_recv().is_err());
+        receiver.request(4, &encode(b, RangeSpecSeq::all()));
+        receiver.finished(4, false);
+        assert!(done_recv.try_recv().is_err());
+    }
+}
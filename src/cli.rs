@@ -0,0 +1,807 @@
+//! Headless mode, for build servers and ssh sessions.
+use std::{
+    collections::HashMap,
+    io::IsTerminal,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use iroh_bytes::{provider::Event, store::ImportProgress, Hash};
+use iroh_net::{key::PublicKey, ticket::BlobTicket};
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+use tracing::error;
+use url::Url;
+
+use sendme::{
+    progress::TransferProgress, rendezvous, Allowlist, Download, Format, GetProgress, InboxTicket,
+    RelayMode, Rendezvous, SendmeError, SharePolicy, ShortCode,
+};
+
+use crate::{config::Config, format_duration, format_size};
+
+/// The transfer failed.
+const EXIT_FAILURE: i32 = 1;
+/// The transfer was interrupted with ctrl-c.
+const EXIT_INTERRUPTED: i32 = 130;
+
+/// Send your files. Starts the app if no command is given.
+#[derive(Parser, Debug)]
+#[command(version)]
+pub struct Args {
+    /// Print newline delimited JSON events to stdout instead of text.
+    #[arg(long, global = true)]
+    pub json: bool,
+    /// Encoding of hashes in the output, hex or cid.
+    #[arg(long, global = true, default_value_t = Format::Hex)]
+    pub format: Format,
+    /// Relay server to use instead of the public ones. Can be repeated, and
+    /// defaults to the relay setting of the app.
+    #[arg(
+        long = "relay",
+        global = true,
+        env = "SENDME_RELAY",
+        value_name = "URL",
+        value_delimiter = ','
+    )]
+    pub relays: Vec<Url>,
+    /// Only use direct addresses, e.g. on a LAN without internet access.
+    /// Takes precedence over --relay.
+    #[arg(long, global = true)]
+    pub no_relay: bool,
+    /// Where short codes are published and looked up, e.g.
+    /// tcp://codes.example.com:7474 or file:///mnt/shared/codes. Defaults to
+    /// the rendezvous setting of the app.
+    #[arg(long, global = true, env = "SENDME_RENDEZVOUS", value_name = "URL")]
+    pub rendezvous: Option<Url>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Share files and directories until interrupted with ctrl-c, or until
+    /// the share expires or reaches its download limit.
+    Send {
+        /// Files and directories to share.
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        /// Only serve the receiver with this node id, refusing everyone else.
+        /// Can be repeated.
+        #[arg(long = "allow", value_name = "NODE_ID")]
+        allowed: Vec<PublicKey>,
+        /// Stop sharing after this long, e.g. 90s, 30m, 2h or 7d.
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        expire_after: Option<Duration>,
+        /// Stop sharing once this many receivers got all files.
+        #[arg(long, value_name = "N", conflicts_with = "once")]
+        max_downloads: Option<u64>,
+        /// Stop sharing once the first receiver got all files.
+        #[arg(long)]
+        once: bool,
+    },
+    /// Send files and directories to the inbox of another device, and exit
+    /// once it downloaded them.
+    Push {
+        /// The inbox ticket shown by the receiver.
+        to: InboxTicket,
+        /// Files and directories to send.
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Download the files of a ticket.
+    Receive {
+        /// The ticket or short code printed by the sender.
+        ticket: String,
+        /// Directory to save the files to.
+        #[arg(short, long, default_value = ".")]
+        out: PathBuf,
+    },
+    /// Run a rendezvous server, which keeps the short codes of shares until
+    /// interrupted with ctrl-c.
+    Rendezvous {
+        /// Address to listen on.
+        #[arg(long, default_value = "0.0.0.0:7474")]
+        listen: SocketAddr,
+    },
+}
+
+/// An event in `--json` mode, printed as a single line.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum JsonEvent {
+    ImportProgress {
+        done: u64,
+        total: u64,
+    },
+    Imported {
+        name: String,
+        hash: String,
+        size: u64,
+    },
+    TicketReady {
+        ticket: String,
+        hash: String,
+        size: u64,
+        files: Vec<JsonFile>,
+    },
+    CodeReady {
+        code: String,
+    },
+    /// The short code given to `receive` stands for this ticket. Codes are
+    /// used up, so resuming the download needs the ticket.
+    CodeResolved {
+        code: String,
+        ticket: String,
+    },
+    PeerConnected {
+        connection_id: u64,
+    },
+    PeerDone {
+        connection_id: u64,
+    },
+    PeerAborted {
+        connection_id: u64,
+    },
+    OfferAccepted,
+    OfferRejected,
+    /// The share stopped because it expired or reached its download limit.
+    ShareEnded {
+        downloads: u64,
+    },
+    Resuming {
+        present: u64,
+        total: u64,
+    },
+    DownloadProgress {
+        done: u64,
+        total: u64,
+    },
+    FileCompleted {
+        name: String,
+        hash: String,
+        size: u64,
+    },
+    Done {
+        files: usize,
+        bytes_read: u64,
+        /// Seconds.
+        elapsed: f64,
+    },
+    Interrupted,
+    Error {
+        /// See [`SendmeError::kind`].
+        kind: &'static str,
+        message: String,
+    },
+}
+
+#[derive(Debug, Serialize)]
+struct JsonFile {
+    name: String,
+    hash: String,
+}
+
+/// How a command reports what it is doing.
+#[derive(Debug, Clone, Copy)]
+struct Output {
+    json: bool,
+    format: Format,
+}
+
+impl Output {
+    fn hash(&self, hash: &Hash) -> String {
+        sendme::print_hash(hash, self.format)
+    }
+
+    /// Print an event to stdout, in json mode.
+    fn emit(&self, event: JsonEvent) {
+        if self.json {
+            match serde_json::to_string(&event) {
+                Ok(line) => println!("{}", line),
+                Err(err) => error!("failed to encode {:?}: {}", event, err),
+            }
+        }
+    }
+
+    /// Print a message for humans to stderr, in text mode.
+    fn info(&self, msg: impl std::fmt::Display) {
+        if !self.json {
+            eprintln!("{}", msg);
+        }
+    }
+}
+
+/// Run the command of `args` to completion and return the exit code of the
+/// process.
+///
+/// `config` holds the settings of the app, which the flags override.
+pub fn run(args: Args, config: Config) -> i32 {
+    let output = Output {
+        json: args.json,
+        format: args.format,
+    };
+    let relay = if args.no_relay {
+        RelayMode::Disabled
+    } else if !args.relays.is_empty() {
+        RelayMode::Custom(args.relays)
+    } else {
+        config.relay.clone()
+    };
+    let rendezvous = args.rendezvous.or_else(|| config.rendezvous.clone());
+    let Some(command) = args.command else {
+        return 0;
+    };
+    let res = tokio::runtime::Runtime::new()
+        .context("failed to start runtime")
+        .and_then(|rt| {
+            rt.block_on(async move {
+                match command {
+                    Command::Send {
+                        paths,
+                        allowed,
+                        expire_after,
+                        max_downloads,
+                        once,
+                    } => {
+                        let allowlist = if allowed.is_empty() {
+                            Allowlist::anyone()
+                        } else {
+                            Allowlist::only(allowed)
+                        };
+                        let policy = SharePolicy {
+                            expires_after: expire_after,
+                            max_downloads: if once { Some(1) } else { max_downloads },
+                        };
+                        send(paths, allowlist, policy, None, relay, rendezvous, output).await
+                    }
+                    Command::Push { to, paths } => {
+                        // only the inbox may fetch the files, and only once
+                        let allowlist = Allowlist::only([to.0.node_id]);
+                        let policy = SharePolicy {
+                            max_downloads: Some(1),
+                            ..Default::default()
+                        };
+                        let to = Some((to, config.device_name()));
+                        send(paths, allowlist, policy, to, relay, None, output).await
+                    }
+                    Command::Receive { ticket, out } => {
+                        // the rendezvous only matters for short codes
+                        let code = ticket.parse::<ShortCode>().ok();
+                        let rendezvous = match code {
+                            Some(_) => open_rendezvous(rendezvous.as_ref())?,
+                            None => None,
+                        };
+                        let ticket = rendezvous::resolve(&ticket, rendezvous.as_deref()).await?;
+                        receive(ticket, code, out, relay, output).await
+                    }
+                    Command::Rendezvous { listen } => serve_rendezvous(listen, output).await,
+                }
+            })
+        });
+    match res {
+        Ok(code) => code,
+        Err(err) => {
+            let kind = err
+                .downcast_ref::<SendmeError>()
+                .map_or("other", SendmeError::kind);
+            output.emit(JsonEvent::Error {
+                kind,
+                message: format!("{:#}", err),
+            });
+            output.info(format_args!("error: {:#}", err));
+            EXIT_FAILURE
+        }
+    }
+}
+
+/// Share `paths` with the receivers on `allowlist`, until `policy` ends the
+/// share or it is interrupted. If `to` is given, offer them to that inbox as
+/// the named device and stop once it downloaded them. Otherwise publish a
+/// short code at `rendezvous`, if given.
+///
+/// A push waits for `policy` to end the share, so it should only allow the
+/// inbox and stop after one download.
+async fn send(
+    paths: Vec<PathBuf>,
+    allowlist: Allowlist,
+    policy: SharePolicy,
+    to: Option<(InboxTicket, String)>,
+    relay: RelayMode,
+    rendezvous: Option<Url>,
+    output: Output,
+) -> Result<i32> {
+    let cancel = CancellationToken::new();
+    tokio::spawn({
+        let cancel = cancel.clone();
+        async move {
+            tokio::signal::ctrl_c().await.ok();
+            cancel.cancel();
+        }
+    });
+
+    let total = paths
+        .iter()
+        .map(|path| sendme::path_stats(path).map(|stats| stats.size))
+        .sum::<sendme::Result<u64>>()?;
+    let (progress_send, progress_recv) = flume::bounded(32);
+    let reporter = tokio::spawn(async move {
+        let mut progress = TransferProgress::new(total);
+        let mut reporter = ProgressReporter::new(output, "importing", |done, total| {
+            JsonEvent::ImportProgress { done, total }
+        });
+        // names and sizes of the files being imported, by id
+        let mut files = HashMap::<u64, (String, u64)>::new();
+        while let Ok(ev) = progress_recv.recv_async().await {
+            match ev {
+                ImportProgress::Found { id, ref name } => {
+                    files.insert(id, (name.clone(), 0));
+                }
+                ImportProgress::Size { id, size } => {
+                    if let Some(file) = files.get_mut(&id) {
+                        file.1 = size;
+                    }
+                }
+                ImportProgress::OutboardDone { id, hash } => {
+                    if let Some((name, size)) = files.remove(&id) {
+                        output.emit(JsonEvent::Imported {
+                            name,
+                            hash: output.hash(&hash),
+                            size,
+                        });
+                    }
+                }
+                _ => {}
+            }
+            progress.on_import(ev);
+            reporter.report(&progress);
+        }
+        reporter.finish(&progress);
+    });
+    let (events_send, events_recv) = flume::unbounded();
+    tokio::spawn(async move {
+        while let Ok(event) = events_recv.recv_async().await {
+            match event {
+                Event::ClientConnected { connection_id, .. } => {
+                    output.emit(JsonEvent::PeerConnected { connection_id });
+                    output.info(format_args!("peer #{} connected", connection_id));
+                }
+                Event::TransferCompleted { connection_id, .. } => {
+                    output.emit(JsonEvent::PeerDone { connection_id });
+                    output.info(format_args!("peer #{} done", connection_id));
+                }
+                Event::TransferAborted { connection_id, .. } => {
+                    output.emit(JsonEvent::PeerAborted { connection_id });
+                    output.info(format_args!("peer #{} aborted", connection_id));
+                }
+                _ => {}
+            }
+        }
+    });
+
+    let res = sendme::provide(
+        paths,
+        relay,
+        allowlist,
+        policy,
+        progress_send,
+        events_send,
+        cancel.clone(),
+    )
+    .await;
+    reporter.await.ok();
+    let share = match res {
+        Ok(share) => share,
+        Err(SendmeError::Cancelled) => {
+            output.emit(JsonEvent::Interrupted);
+            output.info("interrupted");
+            return Ok(EXIT_INTERRUPTED);
+        }
+        Err(err) => return Err(err.into()),
+    };
+    let hash = output.hash(&share.ticket.hash());
+    output.emit(JsonEvent::TicketReady {
+        ticket: share.ticket.to_string(),
+        hash: hash.clone(),
+        size: share.size,
+        files: share
+            .collection
+            .iter()
+            .map(|(name, hash)| JsonFile {
+                name: name.clone(),
+                hash: output.hash(hash),
+            })
+            .collect(),
+    });
+    output.info(format_args!(
+        "imported {} files, {}, hash {}",
+        share.collection.len(),
+        format_size(share.size),
+        hash
+    ));
+    for (name, hash) in share.collection.iter() {
+        output.info(format_args!("    {} {}", output.hash(hash), name));
+    }
+    let Some((to, name)) = to else {
+        if !output.json {
+            println!("sendme receive {}", share.ticket);
+        }
+        let rendezvous = match open_rendezvous(rendezvous.as_ref()) {
+            Ok(rendezvous) => rendezvous,
+            Err(err) => {
+                share.stop().await.ok();
+                return Err(err);
+            }
+        };
+        let code = match &rendezvous {
+            Some(rendezvous) => match rendezvous::publish(rendezvous.as_ref(), &share.ticket).await
+            {
+                Ok(code) => {
+                    output.emit(JsonEvent::CodeReady {
+                        code: code.to_string(),
+                    });
+                    if !output.json {
+                        println!("sendme receive {}", code);
+                    }
+                    Some(code)
+                }
+                Err(err) => {
+                    share.stop().await.ok();
+                    return Err(anyhow::Error::new(err).context("failed to publish short code"));
+                }
+            },
+            None => None,
+        };
+        output.info("sharing, press ctrl-c to stop");
+
+        let interrupted = tokio::select! {
+            _ = cancel.cancelled() => true,
+            _ = share.ended() => false,
+        };
+        if interrupted {
+            output.emit(JsonEvent::Interrupted);
+            output.info("stopping");
+        } else {
+            output.emit(JsonEvent::ShareEnded {
+                downloads: share.downloads(),
+            });
+            output.info("share ended by its expiry or download limit");
+        }
+        if let (Some(rendezvous), Some(code)) = (&rendezvous, &code) {
+            if let Err(err) = rendezvous.remove(code, &share.ticket).await {
+                error!("failed to remove code {}: {:?}", code, err);
+            }
+        }
+        share.stop().await?;
+        return Ok(0);
+    };
+
+    output.info(format_args!(
+        "waiting for {} to accept, press ctrl-c to stop",
+        to.0.node_id
+    ));
+    let res = tokio::select! {
+        res = share.offer(to.0, name) => res.map(Some),
+        _ = cancel.cancelled() => Ok(None),
+    };
+    let code = match res {
+        Ok(Some(true)) => {
+            output.emit(JsonEvent::OfferAccepted);
+            output.info("accepted, sending");
+            tokio::select! {
+                _ = share.ended() => {
+                    output.info("downloaded");
+                    0
+                }
+                _ = cancel.cancelled() => {
+                    output.emit(JsonEvent::Interrupted);
+                    output.info("interrupted");
+                    EXIT_INTERRUPTED
+                }
+            }
+        }
+        Ok(Some(false)) => {
+            output.emit(JsonEvent::OfferRejected);
+            output.info("rejected");
+            EXIT_FAILURE
+        }
+        Ok(None) => {
+            output.emit(JsonEvent::Interrupted);
+            output.info("interrupted");
+            EXIT_INTERRUPTED
+        }
+        Err(err) => {
+            share.stop().await.ok();
+            return Err(err.into());
+        }
+    };
+    share.stop().await?;
+    Ok(code)
+}
+
+/// Download `ticket` to `out`. `code` is the short code the ticket was looked
+/// up with, if any.
+async fn receive(
+    ticket: BlobTicket,
+    code: Option<ShortCode>,
+    out: PathBuf,
+    relay: RelayMode,
+    output: Output,
+) -> Result<i32> {
+    std::fs::create_dir_all(&out).with_context(|| format!("failed to create {}", out.display()))?;
+    if let Some(code) = &code {
+        output.emit(JsonEvent::CodeResolved {
+            code: code.to_string(),
+            ticket: ticket.to_string(),
+        });
+    }
+    // a code can only be looked up once, so resuming needs the ticket
+    let resume = match code {
+        Some(_) => format!("sendme receive {} --out {}", ticket, out.display()),
+        None => "the same command".to_string(),
+    };
+
+    let (send, recv) = flume::bounded(32);
+    tokio::spawn(async move {
+        let mut progress = TransferProgress::new(0);
+        let mut reporter = ProgressReporter::new(output, "downloading", |done, total| {
+            JsonEvent::DownloadProgress { done, total }
+        });
+        while let Ok(ev) = recv.recv_async().await {
+            match ev {
+                GetProgress::Resuming { present, total } => {
+                    output.emit(JsonEvent::Resuming { present, total });
+                    output.info(format_args!(
+                        "resuming, {} of {} already present",
+                        format_size(present),
+                        format_size(total)
+                    ));
+                }
+                GetProgress::FileDone(ref file) => {
+                    output.emit(JsonEvent::FileCompleted {
+                        name: file.name.clone(),
+                        hash: output.hash(&file.hash),
+                        size: file.size,
+                    });
+                }
+                _ => {}
+            }
+            progress.on_get(ev);
+            reporter.report(&progress);
+        }
+        reporter.finish(&progress);
+    });
+
+    // the download is not cancelled on ctrl-c, but dropped with the runtime.
+    // This keeps the partial store, so downloading the ticket again resumes it.
+    let download = Download::start(ticket, out, None, relay, send);
+    let stats = tokio::select! {
+        res = download.finish() => res?,
+        _ = tokio::signal::ctrl_c() => {
+            output.emit(JsonEvent::Interrupted);
+            output.info(format_args!("\ninterrupted, run {} to resume", resume));
+            return Ok(EXIT_INTERRUPTED);
+        }
+    };
+    output.emit(JsonEvent::Done {
+        files: stats.files.len(),
+        bytes_read: stats.bytes_read,
+        elapsed: stats.elapsed.as_secs_f64(),
+    });
+    let size = stats.files.iter().map(|file| file.size).sum::<u64>();
+    output.info(format_args!(
+        "downloaded {} files, {}. took {} ({}/s)",
+        stats.files.len(),
+        format_size(size),
+        format_duration(stats.elapsed),
+        format_size((stats.bytes_read as f64 / stats.elapsed.as_secs_f64()) as u64)
+    ));
+    Ok(0)
+}
+
+/// Create the rendezvous at `url`, if one is set.
+fn open_rendezvous(url: Option<&Url>) -> Result<Option<Arc<dyn Rendezvous>>> {
+    let Some(url) = url else {
+        return Ok(None);
+    };
+    let rendezvous = rendezvous::from_url(url).context("invalid rendezvous")?;
+    Ok(Some(rendezvous))
+}
+
+async fn serve_rendezvous(listen: SocketAddr, output: Output) -> Result<i32> {
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("failed to listen on {}", listen))?;
+    output.info(format_args!(
+        "rendezvous listening on {}, press ctrl-c to stop",
+        listener.local_addr()?
+    ));
+    tokio::select! {
+        res = rendezvous::serve(listener) => res?,
+        _ = tokio::signal::ctrl_c() => {}
+    }
+    output.emit(JsonEvent::Interrupted);
+    output.info("stopping");
+    Ok(0)
+}
+
+/// Parse a duration like `90s`, `30m`, `2h` or `7d`.
+fn parse_duration(text: &str) -> Result<Duration> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (value, unit) = text.split_at(split);
+    let value = value
+        .parse::<u64>()
+        .with_context(|| format!("invalid duration {:?}", text))?;
+    let secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => anyhow::bail!("invalid duration {:?}, use s, m, h or d", text),
+    };
+    let secs = value
+        .checked_mul(secs)
+        .with_context(|| format!("duration {:?} is too long", text))?;
+    Ok(Duration::from_secs(secs))
+}
+
+/// Reports progress a few times per second.
+///
+/// In text mode the progress is printed to stderr on a single line, if stderr
+/// is a terminal. In json mode it is emitted as an event.
+struct ProgressReporter {
+    output: Output,
+    label: &'static str,
+    /// Makes the json event from the bytes done and the total.
+    event: fn(u64, u64) -> JsonEvent,
+    last: Option<Instant>,
+}
+
+impl ProgressReporter {
+    fn new(output: Output, label: &'static str, event: fn(u64, u64) -> JsonEvent) -> Self {
+        Self {
+            output,
+            label,
+            event,
+            last: None,
+        }
+    }
+
+    fn report(&mut self, progress: &TransferProgress) {
+        let now = Instant::now();
+        if progress.total() == 0
+            || self
+                .last
+                .is_some_and(|last| now - last < Duration::from_millis(250))
+        {
+            return;
+        }
+        self.last = Some(now);
+        let (done, total) = (progress.done(), progress.total());
+        if self.output.json {
+            self.output.emit((self.event)(done, total));
+            return;
+        }
+        if !std::io::stderr().is_terminal() {
+            return;
+        }
+        let eta = progress
+            .eta()
+            .map(|eta| format!(", {} left", format_duration(eta)))
+            .unwrap_or_default();
+        // pad to overwrite the rest of a longer previous line
+        eprint!(
+            "\r{} {} of {}, {}/s{}    ",
+            self.label,
+            format_size(done),
+            format_size(total),
+            format_size(progress.throughput() as u64),
+            eta
+        );
+    }
+
+    /// Report the final progress and end the line.
+    fn finish(&mut self, progress: &TransferProgress) {
+        if self.last.is_some() {
+            self.last = None;
+            self.report(progress);
+            if !self.output.json && std::io::stderr().is_terminal() {
+                eprintln!();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh_net::key::SecretKey;
+    use sendme::{identity, Inbox};
+
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration(" 2h").unwrap(), Duration::from_secs(7200));
+        assert_eq!(parse_duration("7d").unwrap(), Duration::from_secs(604800));
+        assert!(parse_duration("2").is_err());
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("2w").is_err());
+        assert!(parse_duration(&format!("{}d", u64::MAX / 60)).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn push_exits_once_the_inbox_downloaded_the_files() {
+        // the inbox and its download need the same node id
+        std::env::set_var(identity::SECRET_ENV, SecretKey::generate().to_string());
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("hello.txt");
+        std::fs::write(&file, b"hello world").unwrap();
+        let target = dir.path().join("downloads");
+        std::fs::create_dir(&target).unwrap();
+
+        let (offers_send, offers) = flume::unbounded();
+        let inbox = Inbox::start(RelayMode::Disabled, offers_send)
+            .await
+            .unwrap();
+        inbox.set_open(true);
+        let to = inbox.ticket(&RelayMode::Disabled).await.unwrap();
+        let download = tokio::spawn({
+            let target = target.clone();
+            async move {
+                let offer = offers.recv_async().await.unwrap();
+                let ticket = offer.ticket.clone();
+                offer.accept();
+                let (progress, _progress) = flume::unbounded();
+                sendme::get(
+                    ticket,
+                    target,
+                    None,
+                    RelayMode::Disabled,
+                    progress,
+                    CancellationToken::new(),
+                )
+                .await
+            }
+        });
+
+        // what `sendme push` runs
+        let allowlist = Allowlist::only([to.0.node_id]);
+        let policy = SharePolicy {
+            max_downloads: Some(1),
+            ..Default::default()
+        };
+        let output = Output {
+            json: true,
+            format: Format::Hex,
+        };
+        let to = Some((to, "test".to_string()));
+        let push = send(
+            vec![file],
+            allowlist,
+            policy,
+            to,
+            RelayMode::Disabled,
+            None,
+            output,
+        );
+        let code = tokio::time::timeout(Duration::from_secs(30), push)
+            .await
+            .expect("push did not exit")
+            .unwrap();
+        assert_eq!(code, 0);
+        download.await.unwrap().unwrap();
+        assert_eq!(
+            std::fs::read(target.join("hello.txt")).unwrap(),
+            b"hello world"
+        );
+        inbox.stop().await;
+    }
+}
@@ -0,0 +1,370 @@
+//! Importing files into collections and exporting them again.
+use std::{
+    collections::HashSet,
+    fmt::{Display, Formatter},
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::Context;
+use futures::StreamExt;
+use iroh_bytes::{
+    format::collection::Collection,
+    store::{ExportMode, ImportMode, ImportProgress},
+    BlobFormat, Hash, TempTag,
+};
+use walkdir::WalkDir;
+
+use crate::{
+    error::{Result, SendmeError},
+    get::{FileInfo, GetProgress},
+};
+
+/// How hashes are shown, see [`print_hash`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Lowercase hex.
+    #[default]
+    Hex,
+    /// The default encoding of iroh hashes.
+    Cid,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "hex" => Ok(Format::Hex),
+            "cid" => Ok(Format::Cid),
+            _ => Err(anyhow::anyhow!("invalid format")),
+        }
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Format::Hex => write!(f, "hex"),
+            Format::Cid => write!(f, "cid"),
+        }
+    }
+}
+
+/// Encode a hash for display.
+pub fn print_hash(hash: &Hash, format: Format) -> String {
+    match format {
+        Format::Hex => hash.to_hex().to_string(),
+        Format::Cid => hash.to_string(),
+    }
+}
+
+/// Check that `component` is a plain file name on every platform, so it can
+/// not leave the directory it is saved to.
+fn validate_path_component(component: &str) -> anyhow::Result<()> {
+    let mut components = Path::new(component).components();
+    let is_normal = matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(name)), None) if name.to_str() == Some(component)
+    );
+    anyhow::ensure!(
+        is_normal && !component.contains(['/', '\\', ':']),
+        "invalid path component {:?}",
+        component
+    );
+    Ok(())
+}
+
+/// This function converts an already canonicalized path to a string.
+///
+/// If `must_be_relative` is true, the function will fail if any component of the path is
+/// `Component::RootDir`
+///
+/// This function will also fail if the path is non canonical, i.e. contains
+/// `..` or `.`, or if the path components contain any windows or unix path
+/// separators.
+pub fn canonicalized_path_to_string(
+    path: impl AsRef<Path>,
+    must_be_relative: bool,
+) -> anyhow::Result<String> {
+    let mut path_str = String::new();
+    let parts = path
+        .as_ref()
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(x) => {
+                let c = match x.to_str() {
+                    Some(c) => c,
+                    None => return Some(Err(anyhow::anyhow!("invalid character in path"))),
+                };
+
+                if !c.contains('/') && !c.contains('\\') {
+                    Some(Ok(c))
+                } else {
+                    Some(Err(anyhow::anyhow!("invalid path component {:?}", c)))
+                }
+            }
+            Component::RootDir => {
+                if must_be_relative {
+                    Some(Err(anyhow::anyhow!("invalid path component {:?}", c)))
+                } else {
+                    path_str.push('/');
+                    None
+                }
+            }
+            _ => Some(Err(anyhow::anyhow!("invalid path component {:?}", c))),
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let parts = parts.join("/");
+    path_str.push_str(&parts);
+    Ok(path_str)
+}
+
+/// Number of files and total size below a path.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PathStats {
+    pub files: u64,
+    pub size: u64,
+}
+
+/// Count the files below `path` and sum up their sizes.
+///
+/// This walks the path the same way [`import`] does, so the numbers match what
+/// ends up in the collection.
+pub fn path_stats(path: &Path) -> Result<PathStats> {
+    let mut stats = PathStats::default();
+    for entry in WalkDir::new(path) {
+        let entry = entry.map_err(|err| SendmeError::path(path, err))?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        stats.files += 1;
+        stats.size += entry
+            .metadata()
+            .map_err(|err| SendmeError::path(entry.path(), err))?
+            .len();
+    }
+    Ok(stats)
+}
+
+/// Pick a name for a top level entry that does not clash with the names in `taken`.
+///
+/// Clashing names get a ` (n)` suffix, inserted before the extension for files.
+fn unique_root_name(name: &str, is_file: bool, taken: &mut HashSet<String>) -> String {
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if is_file && !stem.is_empty() => (stem, Some(ext)),
+        _ => (name, None),
+    };
+    let mut candidate = name.to_string();
+    let mut i = 1;
+    while taken.contains(&candidate) {
+        candidate = match ext {
+            Some(ext) => format!("{stem} ({i}).{ext}"),
+            None => format!("{stem} ({i})"),
+        };
+        i += 1;
+    }
+    taken.insert(candidate.clone());
+    candidate
+}
+
+/// Import files and directories into the database.
+///
+/// The returned tag always refers to a collection. Each input file is a blob
+/// in the collection, named like the file. Each input directory contributes
+/// all the files in the directory, prefixed with the directory name.
+///
+/// Top level names that clash, e.g. two files called `notes.txt` from
+/// different directories, get a ` (n)` suffix.
+pub async fn import(
+    paths: Vec<PathBuf>,
+    db: impl iroh_bytes::store::Store,
+    send: flume::Sender<ImportProgress>,
+) -> Result<(TempTag, u64, Collection)> {
+    if paths.is_empty() {
+        return Err(SendmeError::other(anyhow::anyhow!("nothing to import")));
+    }
+    let mut seen = HashSet::new();
+    let mut taken = HashSet::new();
+    let mut data_sources: Vec<(String, PathBuf)> = Vec::new();
+    for path in paths {
+        let path = path
+            .canonicalize()
+            .context("path does not exist")
+            .map_err(|err| SendmeError::path(&path, err))?;
+        if !seen.insert(path.clone()) {
+            // the same path was given twice
+            continue;
+        }
+        let root_name = path
+            .file_name()
+            .context("path has no name")
+            .and_then(|name| canonicalized_path_to_string(name, true))
+            .map_err(|err| SendmeError::path(&path, err))?;
+        let root_name = unique_root_name(&root_name, path.is_file(), &mut taken);
+        // walkdir also works for files, so we don't need to special case them
+        let files = WalkDir::new(path.clone()).into_iter();
+        // flatten the directory structure into a list of (name, path) pairs.
+        // ignore symlinks.
+        for entry in files {
+            let entry = entry.map_err(|err| SendmeError::path(&path, err))?;
+            if !entry.file_type().is_file() {
+                // Skip symlinks. Directories are handled by WalkDir.
+                continue;
+            }
+            let file = entry.into_path();
+            let relative = file.strip_prefix(&path).map_err(SendmeError::other)?;
+            let name = if relative.as_os_str().is_empty() {
+                root_name.clone()
+            } else {
+                let relative = canonicalized_path_to_string(relative, true)
+                    .map_err(|err| SendmeError::path(&file, err))?;
+                format!("{}/{}", root_name, relative)
+            };
+            data_sources.push((name, file));
+        }
+    }
+    let progress = iroh_bytes::util::progress::FlumeProgressSender::new(send);
+    // import all the files, using num_cpus workers, return names and temp tags
+    let names_and_tags = futures::stream::iter(data_sources)
+        .map(|(name, path)| {
+            let db = db.clone();
+            let progress = progress.clone();
+            async move {
+                let (temp_tag, file_size) = db
+                    .import_file(path, ImportMode::TryReference, BlobFormat::Raw, progress)
+                    .await
+                    .map_err(SendmeError::store)?;
+                Ok((name, temp_tag, file_size))
+            }
+        })
+        .buffer_unordered(num_cpus::get())
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?;
+    drop(progress);
+    // total size of all files
+    let size = names_and_tags.iter().map(|(_, _, size)| *size).sum::<u64>();
+    // collect the (name, hash) tuples into a collection
+    // we must also keep the tags around so the data does not get gced.
+    let (collection, tags) = names_and_tags
+        .into_iter()
+        .map(|(name, tag, _)| ((name, *tag.hash()), tag))
+        .unzip::<_, _, Collection, Vec<_>>();
+    let temp_tag = collection
+        .clone()
+        .store(&db)
+        .await
+        .map_err(SendmeError::store)?;
+    // now that the collection is stored, we can drop the tags
+    // data is protected by the collection
+    drop(tags);
+    Ok((temp_tag, size, collection))
+}
+
+/// The path below `root` a file with the collection name `name` is saved to.
+///
+/// Fails with [`SendmeError::PathRejected`] if a part of the name is not a
+/// plain file name, such as `..`, an empty part or `C:`.
+pub fn get_export_path(root: &Path, name: &str) -> Result<PathBuf> {
+    let mut path = root.to_path_buf();
+    for part in name.split('/') {
+        validate_path_component(part).map_err(|err| SendmeError::path(name, err))?;
+        path.push(part);
+    }
+    Ok(path)
+}
+
+/// The paths below `root` that `files` are saved to.
+///
+/// Fails with [`SendmeError::TargetExists`] if any of them exists.
+pub fn export_targets(root: &Path, files: &[FileInfo]) -> Result<Vec<PathBuf>> {
+    let targets = files
+        .iter()
+        .map(|file| get_export_path(root, &file.name))
+        .collect::<Result<Vec<_>>>()?;
+    if let Some(target) = targets.iter().find(|target| target.exists()) {
+        return Err(SendmeError::TargetExists(target.clone()));
+    }
+    Ok(targets)
+}
+
+/// Save `files` below `root`, reporting each saved file to `send`.
+///
+/// Existing files are never overwritten. If any of the targets exists,
+/// nothing is saved.
+pub async fn export(
+    root: &Path,
+    db: impl iroh_bytes::store::Store,
+    files: &[FileInfo],
+    send: &flume::Sender<GetProgress>,
+) -> Result<()> {
+    let targets = export_targets(root, files)?;
+    for (file, target) in files.iter().zip(targets) {
+        db.export(file.hash, target, ExportMode::TryReference, |_position| {
+            Ok(())
+        })
+        .await
+        .map_err(SendmeError::store)?;
+        send.send_async(GetProgress::FileDone(file.clone()))
+            .await
+            .ok();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clashing_root_names_get_a_suffix() {
+        let mut taken = HashSet::new();
+        let mut name = |name: &str, is_file: bool| unique_root_name(name, is_file, &mut taken);
+        assert_eq!(name("notes.txt", true), "notes.txt");
+        assert_eq!(name("notes.txt", true), "notes (1).txt");
+        assert_eq!(name("notes.txt", true), "notes (2).txt");
+        assert_eq!(name("archive.tar.gz", true), "archive.tar.gz");
+        assert_eq!(name("archive.tar.gz", true), "archive.tar (1).gz");
+        // directories and dot files have no extension to keep
+        assert_eq!(name("photos", false), "photos");
+        assert_eq!(name("photos", false), "photos (1)");
+        assert_eq!(name("v1.2", false), "v1.2");
+        assert_eq!(name("v1.2", false), "v1.2 (1)");
+        assert_eq!(name(".config", true), ".config");
+        assert_eq!(name(".config", true), ".config (1)");
+        // a suffixed name that is taken already is skipped
+        assert_eq!(name("notes (3).txt", true), "notes (3).txt");
+        assert_eq!(name("notes.txt", true), "notes (4).txt");
+    }
+
+    #[test]
+    fn export_path_only_takes_plain_names() {
+        let root = Path::new("out");
+        let path = get_export_path(root, "dir/file.txt").unwrap();
+        assert_eq!(path, root.join("dir").join("file.txt"));
+        for name in [
+            "..",
+            "dir/../..",
+            ".",
+            "dir/./file",
+            "",
+            "dir//file",
+            "dir/",
+            "/etc",
+            "dir\\file",
+            "..\\file",
+            "C:",
+            "C:/file",
+            "c:file",
+        ] {
+            let res = get_export_path(root, name);
+            assert!(
+                matches!(res, Err(SendmeError::PathRejected { .. })),
+                "{:?} was not rejected",
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,67 @@
+//! Settings that are kept across restarts, shared by the app and the command
+//! line.
+use std::{path::PathBuf, sync::Arc};
+
+use anyhow::Context;
+use sendme::{RelayMode, Rendezvous};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// Name of the settings file in the config dir.
+const CONFIG_FILE: &str = "settings.json";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Relay servers used for new shares and downloads.
+    pub relay: RelayMode,
+    /// Name shown to nearby devices, the host name if not set.
+    pub device_name: Option<String>,
+    /// Do not announce this device on the local network.
+    pub hidden: bool,
+    /// Where short codes are published and looked up, e.g.
+    /// `tcp://codes.example.com:7474` or `file:///mnt/shared/codes`.
+    pub rendezvous: Option<Url>,
+}
+
+impl Config {
+    /// The name shown to nearby devices.
+    pub fn device_name(&self) -> String {
+        self.device_name
+            .clone()
+            .unwrap_or_else(sendme::discovery::device_name)
+    }
+
+    /// The rendezvous for short codes, if one is set.
+    pub fn rendezvous(&self) -> anyhow::Result<Option<Arc<dyn Rendezvous>>> {
+        let Some(url) = &self.rendezvous else {
+            return Ok(None);
+        };
+        Ok(Some(sendme::rendezvous::from_url(url)?))
+    }
+
+    fn path() -> anyhow::Result<PathBuf> {
+        Ok(sendme::config_dir()?.join(CONFIG_FILE))
+    }
+
+    /// Load the settings, or the defaults if none were saved yet.
+    pub fn load() -> anyhow::Result<Self> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let json = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        serde_json::from_str(&json)
+            .with_context(|| format!("invalid settings in {}", path.display()))
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let path = Self::path()?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(&path, json).with_context(|| format!("failed to write {}", path.display()))
+    }
+}
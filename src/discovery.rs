@@ -0,0 +1,238 @@
+//! Finding other instances of the app on the local network.
+//!
+//! Every instance broadcasts a small announcement with its device name, and the
+//! node id and direct addresses of its [`Inbox`], on a fixed UDP port, and
+//! listens for the announcements of the others. Devices that stop announcing
+//! are dropped after a few seconds. Announcing can be turned off, which still
+//! lists the other devices.
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use iroh_net::{key::PublicKey, MagicEndpoint, NodeAddr};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::{net::UdpSocket, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use crate::{
+    error::{Result, SendmeError},
+    Inbox,
+};
+
+/// UDP port the announcements are broadcast on.
+pub const DISCOVERY_PORT: u16 = 47294;
+
+/// Identifies announcements of this app, and their version.
+const MAGIC: &str = "sendme/discovery/0";
+
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Devices that have not announced themselves for this long are dropped.
+const DEVICE_TIMEOUT: Duration = Duration::from_secs(7);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Announcement {
+    magic: String,
+    /// Random id of the announcing instance, to ignore our own announcements.
+    instance: u64,
+    name: String,
+    node_id: PublicKey,
+    direct_addresses: Vec<SocketAddr>,
+    /// Whether the inbox accepts offers.
+    inbox: bool,
+}
+
+/// Another instance of the app on the local network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NearbyDevice {
+    /// The name the device announces itself with.
+    pub name: String,
+    /// The node id and direct addresses of the inbox of the device.
+    pub addr: NodeAddr,
+    /// Whether the inbox of the device accepts offers.
+    pub inbox: bool,
+}
+
+/// Announces this device on the local network, created by [`Discovery::start`].
+///
+/// Stops announcing when dropped.
+#[derive(Debug)]
+pub struct Discovery {
+    name: Arc<Mutex<String>>,
+    announce: Arc<AtomicBool>,
+    cancel: CancellationToken,
+    task: JoinHandle<()>,
+}
+
+impl Discovery {
+    /// Announce `inbox` as the device `name`, if `announce` is set, and send
+    /// the list of nearby devices to `nearby` whenever it changes.
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn start(
+        name: String,
+        announce: bool,
+        inbox: &Inbox,
+        nearby: flume::Sender<Vec<NearbyDevice>>,
+    ) -> Result<Self> {
+        let socket = bind_socket()
+            .with_context(|| format!("failed to bind discovery port {}", DISCOVERY_PORT))
+            .map_err(SendmeError::other)?;
+        let endpoint = inbox.endpoint().clone();
+        let open = inbox.open_flag();
+        let name = Arc::new(Mutex::new(name));
+        let announce = Arc::new(AtomicBool::new(announce));
+        let cancel = CancellationToken::new();
+        let task = tokio::spawn({
+            let name = name.clone();
+            let announce = announce.clone();
+            let cancel = cancel.clone();
+            async move {
+                tokio::select! {
+                    _ = run(socket, endpoint, name, announce, open, nearby) => {}
+                    _ = cancel.cancelled() => {}
+                }
+            }
+        });
+        Ok(Self {
+            name,
+            announce,
+            cancel,
+            task,
+        })
+    }
+
+    /// Change the name this device announces itself with.
+    pub fn set_name(&self, name: String) {
+        *self.name.lock().unwrap() = name;
+    }
+
+    /// Start or stop announcing this device. Other devices keep being listed.
+    pub fn set_announce(&self, announce: bool) {
+        self.announce.store(announce, Ordering::Relaxed);
+    }
+
+    /// Stop announcing this device and looking for others.
+    pub async fn stop(self) {
+        self.cancel.cancel();
+        self.task.await.ok();
+    }
+}
+
+impl Drop for Discovery {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+/// The name of this device, as announced by default.
+pub fn device_name() -> String {
+    gethostname::gethostname().to_string_lossy().into_owned()
+}
+
+/// Bind the discovery port, sharing it with other instances on this machine.
+fn bind_socket() -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.set_broadcast(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT)).into())?;
+    UdpSocket::from_std(socket.into())
+}
+
+async fn run(
+    socket: UdpSocket,
+    endpoint: MagicEndpoint,
+    name: Arc<Mutex<String>>,
+    announce: Arc<AtomicBool>,
+    open: Arc<AtomicBool>,
+    nearby: flume::Sender<Vec<NearbyDevice>>,
+) {
+    let instance = rand::thread_rng().gen::<u64>();
+    let broadcast = SocketAddr::from((Ipv4Addr::BROADCAST, DISCOVERY_PORT));
+    let mut devices = HashMap::<PublicKey, (NearbyDevice, Instant)>::new();
+    let mut reported = Vec::new();
+    let mut interval = tokio::time::interval(ANNOUNCE_INTERVAL);
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                if announce.load(Ordering::Relaxed) {
+                    match announcement(instance, &name, &open, &endpoint).await {
+                        Ok(packet) => {
+                            if let Err(err) = socket.send_to(&packet, broadcast).await {
+                                debug!("failed to announce: {:?}", err);
+                            }
+                        }
+                        Err(err) => warn!("failed to make announcement: {:?}", err),
+                    }
+                }
+                devices.retain(|_, (_, seen)| seen.elapsed() < DEVICE_TIMEOUT);
+            }
+            res = socket.recv_from(&mut buf) => {
+                let (len, from) = match res {
+                    Ok(res) => res,
+                    Err(err) => {
+                        debug!("failed to receive announcement: {:?}", err);
+                        continue;
+                    }
+                };
+                let Ok(ann) = serde_json::from_slice::<Announcement>(&buf[..len]) else {
+                    continue;
+                };
+                if ann.magic != MAGIC || ann.instance == instance {
+                    continue;
+                }
+                debug!("announcement of {} from {}", ann.node_id, from);
+                let device = NearbyDevice {
+                    name: ann.name,
+                    addr: NodeAddr::from_parts(ann.node_id, None, ann.direct_addresses),
+                    inbox: ann.inbox,
+                };
+                devices.insert(ann.node_id, (device, Instant::now()));
+            }
+        }
+
+        let mut current = devices
+            .values()
+            .map(|(device, _)| device.clone())
+            .collect::<Vec<_>>();
+        current.sort_by(|a, b| (&a.name, a.addr.node_id).cmp(&(&b.name, b.addr.node_id)));
+        if current != reported {
+            if nearby.send_async(current.clone()).await.is_err() {
+                break;
+            }
+            reported = current;
+        }
+    }
+}
+
+async fn announcement(
+    instance: u64,
+    name: &Mutex<String>,
+    open: &AtomicBool,
+    endpoint: &MagicEndpoint,
+) -> anyhow::Result<Vec<u8>> {
+    let addr = endpoint.my_addr().await?;
+    let ann = Announcement {
+        magic: MAGIC.to_string(),
+        instance,
+        name: name.lock().unwrap().clone(),
+        node_id: addr.node_id,
+        direct_addresses: addr.info.direct_addresses.into_iter().collect(),
+        inbox: open.load(Ordering::Relaxed),
+    };
+    Ok(serde_json::to_vec(&ann)?)
+}
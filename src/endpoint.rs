@@ -0,0 +1,142 @@
+//! Setting up the network endpoints of shares, downloads and the inbox.
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::OnceLock,
+    time::Duration,
+};
+
+use anyhow::Context;
+use iroh_net::{
+    defaults::DEFAULT_DERP_STUN_PORT,
+    derp::{DerpMap, DerpMode, DerpNode},
+    key::SecretKey,
+    MagicEndpoint, NodeAddr,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use url::Url;
+
+use crate::{identity, inbox::INBOX_ALPN};
+
+/// How long a share waits for a relay server before it makes a ticket with
+/// only direct addresses.
+const RELAY_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How long to wait for the endpoint to discover its own addresses.
+const ADDR_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Whether connections may go through a relay server, and through which.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RelayMode {
+    /// Use the public relay servers of n0 when they are reachable, and direct
+    /// addresses otherwise.
+    #[default]
+    Default,
+    /// Only use these relay servers, e.g. ones run by a company.
+    ///
+    /// Traffic never goes through the public relay servers.
+    Custom(Vec<Url>),
+    /// Only connect over direct addresses, e.g. on a LAN without internet.
+    Disabled,
+}
+
+impl RelayMode {
+    fn derp_mode(&self) -> anyhow::Result<DerpMode> {
+        Ok(match self {
+            RelayMode::Default => DerpMode::Default,
+            RelayMode::Custom(urls) => {
+                anyhow::ensure!(!urls.is_empty(), "no relay servers configured");
+                let nodes = urls.iter().map(|url| DerpNode {
+                    url: url.clone(),
+                    stun_only: false,
+                    stun_port: DEFAULT_DERP_STUN_PORT,
+                });
+                DerpMode::Custom(DerpMap::from_nodes(nodes)?)
+            }
+            RelayMode::Disabled => DerpMode::Disabled,
+        })
+    }
+}
+
+/// Bind an endpoint with a new secret key, for a share.
+///
+/// Only the endpoint of [`device`] has the node id of this device, so shares
+/// do not compete with it, or with each other, for that node id at the relay.
+pub(crate) async fn bind(alpns: Vec<Vec<u8>>, relay: &RelayMode) -> anyhow::Result<MagicEndpoint> {
+    MagicEndpoint::builder()
+        .alpns(alpns)
+        .secret_key(SecretKey::generate())
+        .derp_mode(relay.derp_mode()?)
+        .bind(0)
+        .await
+}
+
+/// The endpoint with the secret key of this device, bound on first use.
+///
+/// The inbox, downloads and previews all share it, since the inbox and the
+/// shares that only allow some node ids go by the node id of this device. It
+/// is bound again if the relay mode or the key changed.
+pub(crate) async fn device(relay: &RelayMode) -> anyhow::Result<MagicEndpoint> {
+    static DEVICE: OnceLock<futures::lock::Mutex<Option<(RelayMode, MagicEndpoint)>>> =
+        OnceLock::new();
+    let secret_key = identity::secret_key()?;
+    let mut device = DEVICE
+        .get_or_init(|| futures::lock::Mutex::new(None))
+        .lock()
+        .await;
+    if let Some((mode, endpoint)) = device.as_ref() {
+        if mode == relay && endpoint.node_id() == secret_key.public() {
+            return Ok(endpoint.clone());
+        }
+    }
+    // an endpoint of an earlier relay mode or key stays with the downloads
+    // that still use it
+    let endpoint = MagicEndpoint::builder()
+        .alpns(vec![INBOX_ALPN.to_vec()])
+        .secret_key(secret_key)
+        .derp_mode(relay.derp_mode()?)
+        .bind(0)
+        .await?;
+    *device = Some((relay.clone(), endpoint.clone()));
+    Ok(endpoint)
+}
+
+/// The address of `endpoint` to put in a ticket.
+///
+/// Waits a short time for a relay server, which is then included so peers can
+/// connect through the same relay. Without one, the address only has
+/// the direct addresses of the endpoint, including loopback, so peers on the
+/// same network or machine can still connect.
+pub(crate) async fn node_addr(
+    endpoint: &MagicEndpoint,
+    relay: &RelayMode,
+) -> anyhow::Result<NodeAddr> {
+    if *relay != RelayMode::Disabled {
+        let wait_for_relay = async {
+            while endpoint.my_derp().is_none() {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        };
+        if tokio::time::timeout(RELAY_TIMEOUT, wait_for_relay)
+            .await
+            .is_err()
+        {
+            info!("no relay server reachable, using direct addresses only");
+        }
+    }
+    let mut addr = tokio::time::timeout(ADDR_TIMEOUT, endpoint.my_addr())
+        .await
+        .context("timed out discovering the local addresses")??;
+    // loopback is not among the discovered addresses
+    let (v4, v6) = endpoint.local_addr()?;
+    addr.info
+        .direct_addresses
+        .insert(SocketAddr::from((Ipv4Addr::LOCALHOST, v4.port())));
+    if let Some(v6) = v6 {
+        addr.info
+            .direct_addresses
+            .insert(SocketAddr::from((Ipv6Addr::LOCALHOST, v6.port())));
+    }
+    Ok(addr)
+}
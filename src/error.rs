@@ -0,0 +1,77 @@
+use std::path::PathBuf;
+
+/// Errors returned by the transfer functions of this crate.
+#[derive(Debug, thiserror::Error)]
+pub enum SendmeError {
+    /// The ticket could not be parsed or does not refer to a collection.
+    #[error("invalid ticket")]
+    InvalidTicket(#[source] anyhow::Error),
+    /// The peer of a ticket could not be reached.
+    #[error("could not reach the sender")]
+    PeerUnreachable(#[source] anyhow::Error),
+    /// A path can not be shared, or a received name can not be saved.
+    #[error("{} can not be used", path.display())]
+    PathRejected {
+        path: PathBuf,
+        #[source]
+        source: anyhow::Error,
+    },
+    /// There is not enough space left to store the data.
+    #[error("not enough disk space")]
+    DiskFull(#[source] anyhow::Error),
+    /// A received file would overwrite an existing file.
+    #[error("{} already exists", .0.display())]
+    TargetExists(PathBuf),
+    /// The operation was cancelled.
+    #[error("cancelled")]
+    Cancelled,
+    /// Reading or writing a store failed.
+    #[error("failed to access the store")]
+    StoreIo(#[source] anyhow::Error),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl SendmeError {
+    /// A short, stable name of the error kind, e.g. for machine readable output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SendmeError::InvalidTicket(_) => "invalid_ticket",
+            SendmeError::PeerUnreachable(_) => "peer_unreachable",
+            SendmeError::PathRejected { .. } => "path_rejected",
+            SendmeError::DiskFull(_) => "disk_full",
+            SendmeError::TargetExists(_) => "target_exists",
+            SendmeError::Cancelled => "cancelled",
+            SendmeError::StoreIo(_) => "store_io",
+            SendmeError::Other(_) => "other",
+        }
+    }
+
+    pub(crate) fn other(err: impl Into<anyhow::Error>) -> Self {
+        SendmeError::Other(err.into())
+    }
+
+    /// Classify an error of a store operation, which may be a full disk.
+    pub(crate) fn store(err: impl Into<anyhow::Error>) -> Self {
+        let err = err.into();
+        let disk_full = err
+            .chain()
+            .filter_map(|err| err.downcast_ref::<std::io::Error>())
+            .any(|err| err.kind() == std::io::ErrorKind::StorageFull);
+        if disk_full {
+            SendmeError::DiskFull(err)
+        } else {
+            SendmeError::StoreIo(err)
+        }
+    }
+
+    pub(crate) fn path(path: impl Into<PathBuf>, err: impl Into<anyhow::Error>) -> Self {
+        SendmeError::PathRejected {
+            path: path.into(),
+            source: err.into(),
+        }
+    }
+}
+
+/// Result type of the transfer functions of this crate.
+pub type Result<T, E = SendmeError> = std::result::Result<T, E>;
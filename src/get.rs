@@ -0,0 +1,427 @@
+//! Fetching collections from other devices.
+use std::{collections::HashSet, path::PathBuf};
+
+use anyhow::Context;
+use iroh_bytes::{
+    format::collection::Collection,
+    get::{
+        db::{get_to_db, DownloadProgress},
+        request::get_hash_seq_and_sizes,
+        Stats,
+    },
+    store::{Map, MapEntry},
+    util::progress::IgnoreProgressSender,
+    BlobFormat, Hash, HashAndFormat,
+};
+use iroh_net::ticket::BlobTicket;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use crate::{
+    collection::{export, export_targets},
+    endpoint::{self, RelayMode},
+    error::{Result, SendmeError},
+};
+
+/// Parse a ticket as pasted by a user, ignoring surrounding whitespace.
+///
+/// Fails with [`SendmeError::InvalidTicket`] if it is not a ticket for a
+/// collection.
+pub fn parse_ticket(ticket: &str) -> Result<BlobTicket> {
+    let ticket = ticket
+        .trim()
+        .parse::<BlobTicket>()
+        .map_err(SendmeError::InvalidTicket)?;
+    if ticket.format() != BlobFormat::HashSeq {
+        return Err(SendmeError::InvalidTicket(anyhow::anyhow!(
+            "ticket does not refer to a collection"
+        )));
+    }
+    Ok(ticket)
+}
+
+/// The files in a collection, as listed by [`preview`].
+#[derive(Debug, Clone, Default)]
+pub struct Preview {
+    /// Names and sizes of the files, in collection order.
+    pub files: Vec<(String, u64)>,
+}
+
+impl Preview {
+    /// Total size of the files.
+    pub fn size(&self) -> u64 {
+        self.files.iter().map(|(_, size)| size).sum()
+    }
+}
+
+/// List the files in the collection of `ticket` without downloading them.
+///
+/// Only the hash sequence and the collection metadata are transferred, so
+/// this is cheap even for very large collections.
+pub async fn preview(ticket: &BlobTicket, relay: &RelayMode) -> Result<Preview> {
+    if ticket.format() != BlobFormat::HashSeq {
+        return Err(SendmeError::InvalidTicket(anyhow::anyhow!(
+            "ticket does not refer to a collection"
+        )));
+    }
+    let endpoint = endpoint::device(relay).await?;
+    let connection = endpoint
+        .connect(ticket.node_addr().clone(), iroh_bytes::protocol::ALPN)
+        .await
+        .map_err(SendmeError::PeerUnreachable)?;
+    let (hash_seq, sizes) = get_hash_seq_and_sizes(&connection, &ticket.hash(), 1024 * 1024 * 32)
+        .await
+        .map_err(SendmeError::other)?;
+    let meta = hash_seq
+        .iter()
+        .next()
+        .context("collection has no metadata")?;
+
+    // the metadata is small, fetch it and the hash sequence into memory
+    let db = iroh_bytes::store::mem::Store::new();
+    for hash in [ticket.hash(), meta] {
+        let hash_and_format = HashAndFormat {
+            hash,
+            format: BlobFormat::Raw,
+        };
+        get_to_db(
+            &db,
+            connection.clone(),
+            &hash_and_format,
+            IgnoreProgressSender::default(),
+        )
+        .await
+        .map_err(SendmeError::other)?;
+    }
+    let collection = Collection::load(&db, &ticket.hash())
+        .await
+        .map_err(SendmeError::other)?;
+
+    // the first size is the one of the metadata
+    let files = collection
+        .iter()
+        .zip(sizes.iter().skip(1))
+        .map(|((name, _), size)| (name.clone(), *size))
+        .collect();
+    // the endpoint is shared, only close the connection
+    connection.close(0u32.into(), b"preview done");
+    Ok(Preview { files })
+}
+
+/// Progress events emitted by [`get`].
+#[derive(Debug)]
+pub enum GetProgress {
+    /// The sizes of the blobs in the collection are known.
+    ///
+    /// The first size is the one of the collection metadata, the rest are the
+    /// sizes of the files that are fetched.
+    Sizes(Vec<u64>),
+    /// The names of the files that are fetched, in the order of their sizes.
+    Names(Vec<String>),
+    /// A partial download from an earlier attempt was found.
+    ///
+    /// Data that is already present is not fetched again.
+    Resuming {
+        /// Bytes of the files that are already present.
+        present: u64,
+        /// Total bytes of the files.
+        total: u64,
+    },
+    /// Progress of the download itself.
+    Download(DownloadProgress),
+    /// A file was saved to the target directory.
+    FileDone(FileInfo),
+}
+
+/// A file of a downloaded collection.
+#[derive(Debug, Clone)]
+pub struct FileInfo {
+    /// Name in the collection, with `/` between directories.
+    pub name: String,
+    /// Hash of the contents.
+    pub hash: Hash,
+    /// Size in bytes.
+    pub size: u64,
+}
+
+/// Statistics of a completed [`get`].
+#[derive(Debug, Clone)]
+pub struct GetStats {
+    /// The files that were saved.
+    pub files: Vec<FileInfo>,
+    /// Bytes read from the network, without data that was already present.
+    pub bytes_read: u64,
+    /// Time spent transferring data.
+    pub elapsed: std::time::Duration,
+}
+
+/// Forward download events from `recv` to `send` until the download is done.
+///
+/// Blobs fetched on their own always report child 0. For those, `child` is
+/// the position of the blob in the collection download, and `AllDone` is
+/// dropped, as it only marks the end of that single blob.
+fn forward_download(
+    recv: flume::Receiver<DownloadProgress>,
+    send: flume::Sender<GetProgress>,
+    child: Option<u64>,
+) -> JoinHandle<()> {
+    tokio::task::spawn(async move {
+        while let Ok(ev) = recv.recv_async().await {
+            let ev = match (ev, child) {
+                (DownloadProgress::AllDone, Some(_)) => continue,
+                (DownloadProgress::Found { id, hash, size, .. }, Some(child)) => {
+                    DownloadProgress::Found {
+                        id,
+                        child,
+                        hash,
+                        size,
+                    }
+                }
+                (ev, _) => ev,
+            };
+            if send.send_async(GetProgress::Download(ev)).await.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// Fetch the blob `hash` on its own, reporting its progress as `child` of the
+/// collection download.
+async fn get_blob(
+    db: &iroh_bytes::store::flat::Store,
+    connection: &quinn::Connection,
+    hash: Hash,
+    child: u64,
+    send: &flume::Sender<GetProgress>,
+) -> Result<Stats> {
+    let (download_send, download_recv) = flume::bounded(32);
+    let forward = forward_download(download_recv, send.clone(), Some(child));
+    let progress = iroh_bytes::util::progress::FlumeProgressSender::new(download_send);
+    let hash_and_format = HashAndFormat {
+        hash,
+        format: BlobFormat::Raw,
+    };
+    let stats = get_to_db(db, connection.clone(), &hash_and_format, progress)
+        .await
+        .map_err(SendmeError::other)?;
+    forward.await.ok();
+    Ok(stats)
+}
+
+/// Download the collection of `ticket` into the `target` directory.
+///
+/// If `selection` is given, only the files at these positions in the
+/// collection, as listed by [`preview`], are fetched and exported. Each of
+/// them is requested on its own, so the data of the other files is never
+/// transferred.
+///
+/// With [`RelayMode::Disabled`], the sender is only reached over the direct
+/// addresses in the ticket.
+///
+/// Fails with [`SendmeError::TargetExists`] before any file data is
+/// transferred if a file would overwrite an existing one.
+///
+/// Cancelling `cancel` aborts the download and removes the partial store.
+pub async fn get(
+    ticket: BlobTicket,
+    target: PathBuf,
+    selection: Option<Vec<usize>>,
+    relay: RelayMode,
+    send: flume::Sender<GetProgress>,
+    cancel: CancellationToken,
+) -> Result<GetStats> {
+    let addr = ticket.node_addr().clone();
+    let endpoint = endpoint::device(&relay).await?;
+    let dir_name = format!(".sendme-get-{}", ticket.hash().to_hex());
+    let iroh_data_dir = target.join(dir_name);
+
+    let download = async {
+        // a store from an earlier, interrupted attempt. get_to_db only requests
+        // the ranges that are missing from it.
+        let resuming = iroh_data_dir.exists();
+        let db = iroh_bytes::store::flat::Store::load(&iroh_data_dir)
+            .await
+            .map_err(SendmeError::store)?;
+
+        let connection = endpoint
+            .connect(addr, iroh_bytes::protocol::ALPN)
+            .await
+            .map_err(SendmeError::PeerUnreachable)?;
+        let hash_and_format = HashAndFormat {
+            hash: ticket.hash(),
+            format: ticket.format(),
+        };
+
+        let (hash_seq, sizes) =
+            get_hash_seq_and_sizes(&connection, &hash_and_format.hash, 1024 * 1024 * 32)
+                .await
+                .map_err(SendmeError::other)?;
+        let hashes = hash_seq.iter().collect::<Vec<_>>();
+        // the first blob is the collection metadata, the files follow
+        let wanted = match selection {
+            Some(ref selection) => selection.iter().map(|i| i + 1).collect::<Vec<_>>(),
+            None => (1..hashes.len()).collect(),
+        };
+        if wanted.iter().any(|&i| i >= hashes.len()) {
+            return Err(SendmeError::other(anyhow::anyhow!(
+                "selection does not match the collection"
+            )));
+        }
+        let wanted_sizes = wanted.iter().map(|&i| sizes[i]).collect::<Vec<_>>();
+        let mut progress_sizes = vec![sizes.first().copied().unwrap_or_default()];
+        progress_sizes.extend(&wanted_sizes);
+        send.send_async(GetProgress::Sizes(progress_sizes))
+            .await
+            .ok();
+        if resuming {
+            let present = wanted
+                .iter()
+                .filter(|&&i| db.get(&hashes[i]).is_some_and(|entry| entry.is_complete()))
+                .map(|&i| sizes[i])
+                .sum::<u64>();
+            let total = wanted_sizes.iter().sum::<u64>();
+            info!("resuming, {} of {} bytes already present", present, total);
+            send.send_async(GetProgress::Resuming { present, total })
+                .await
+                .ok();
+        }
+
+        // the hash sequence and the metadata come first, so the names of the
+        // files are known before any of their data is transferred
+        let meta = *hashes
+            .first()
+            .ok_or_else(|| SendmeError::other(anyhow::anyhow!("collection has no metadata")))?;
+        let mut bytes_read = 0;
+        let mut elapsed = std::time::Duration::ZERO;
+        for (child, hash) in [ticket.hash(), meta].into_iter().enumerate() {
+            let stats = get_blob(&db, &connection, hash, child as u64, &send).await?;
+            bytes_read += stats.bytes_read;
+            elapsed += stats.elapsed;
+        }
+        let collection = Collection::load(&db, &hash_and_format.hash)
+            .await
+            .map_err(SendmeError::other)?;
+        let selected = selection
+            .as_ref()
+            .map(|selection| selection.iter().copied().collect::<HashSet<_>>());
+        // the first size is the one of the metadata
+        let files = collection
+            .iter()
+            .zip(sizes.iter().skip(1))
+            .enumerate()
+            .filter(|(i, _)| selected.as_ref().is_none_or(|s| s.contains(i)))
+            .map(|(_, ((name, hash), size))| FileInfo {
+                name: name.clone(),
+                hash: *hash,
+                size: *size,
+            })
+            .collect::<Vec<_>>();
+        if let Err(err) = export_targets(&target, &files) {
+            if !resuming {
+                drop(db);
+                std::fs::remove_dir_all(&iroh_data_dir).ok();
+            }
+            return Err(err);
+        }
+        let names = files.iter().map(|file| file.name.clone()).collect();
+        send.send_async(GetProgress::Names(names)).await.ok();
+
+        match selection {
+            None => {
+                let (download_send, download_recv) = flume::bounded(32);
+                forward_download(download_recv, send.clone(), None);
+                let progress = iroh_bytes::util::progress::FlumeProgressSender::new(download_send);
+                let stats = get_to_db(&db, connection, &hash_and_format, progress)
+                    .await
+                    .map_err(SendmeError::other)?;
+                bytes_read += stats.bytes_read;
+                elapsed += stats.elapsed;
+            }
+            Some(_) => {
+                // after the hash sequence and the metadata
+                for (child, &i) in (2..).zip(&wanted) {
+                    let stats = get_blob(&db, &connection, hashes[i], child, &send).await?;
+                    bytes_read += stats.bytes_read;
+                    elapsed += stats.elapsed;
+                }
+                send.send_async(GetProgress::Download(DownloadProgress::AllDone))
+                    .await
+                    .ok();
+            }
+        }
+        export(&target, db, &files, &send).await?;
+        std::fs::remove_dir_all(&iroh_data_dir).map_err(SendmeError::store)?;
+        info!(
+            "downloaded {} files to {}, read {} bytes in {:?}",
+            files.len(),
+            target.display(),
+            bytes_read,
+            elapsed
+        );
+
+        Ok::<_, SendmeError>(GetStats {
+            files,
+            bytes_read,
+            elapsed,
+        })
+    };
+
+    let res = tokio::select! {
+        res = download => res,
+        _ = cancel.cancelled() => Err(SendmeError::Cancelled),
+    };
+    // the connection was dropped with the download, which closes it
+    if res.is_err() && cancel.is_cancelled() {
+        std::fs::remove_dir_all(&iroh_data_dir).ok();
+    }
+    res
+}
+
+/// A download running in the background, created by [`Download::start`].
+#[derive(Debug)]
+pub struct Download {
+    cancel: CancellationToken,
+    task: JoinHandle<Result<GetStats>>,
+}
+
+impl Download {
+    /// Start downloading the collection of `ticket` into `target`.
+    ///
+    /// See [`get`] for `selection`, `relay` and the events sent to `progress`.
+    /// Must be called from within a tokio runtime.
+    pub fn start(
+        ticket: BlobTicket,
+        target: PathBuf,
+        selection: Option<Vec<usize>>,
+        relay: RelayMode,
+        progress: flume::Sender<GetProgress>,
+    ) -> Self {
+        let cancel = CancellationToken::new();
+        let task = tokio::spawn(get(
+            ticket,
+            target,
+            selection,
+            relay,
+            progress,
+            cancel.clone(),
+        ));
+        Self { cancel, task }
+    }
+
+    /// Abort the download and remove the partial store.
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    /// A token that cancels the download, e.g. to cancel it from another task.
+    pub fn cancel_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    /// Wait for the download to complete.
+    pub async fn finish(self) -> Result<GetStats> {
+        self.task.await.map_err(SendmeError::other)?
+    }
+}
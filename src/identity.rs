@@ -0,0 +1,154 @@
+//! The secret key of this device, which determines its node id.
+//!
+//! The key is generated on first use and kept in the config dir, so peers see
+//! the same node id for downloads, the inbox and offers across restarts.
+//! Shares have keys of their own. `IROH_SECRET` overrides it.
+use std::{
+    fs::OpenOptions,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Mutex,
+};
+
+use anyhow::Context;
+use iroh_net::key::{PublicKey, SecretKey};
+
+use crate::{
+    config_dir,
+    error::{Result, SendmeError},
+};
+
+/// Environment variable that overrides the stored secret key.
+pub const SECRET_ENV: &str = "IROH_SECRET";
+
+/// Name of the key file in the config dir.
+const KEY_FILE: &str = "secret_key";
+
+/// Serializes access to the key file, so concurrent first uses agree on a key.
+static KEY_LOCK: Mutex<()> = Mutex::new(());
+
+/// Path of the file holding the secret key.
+pub fn key_path() -> Result<PathBuf> {
+    Ok(config_dir()?.join(KEY_FILE))
+}
+
+/// Whether the secret key is set with [`SECRET_ENV`] instead of the key file.
+pub fn is_overridden() -> bool {
+    std::env::var(SECRET_ENV).is_ok()
+}
+
+/// Get the secret key, generating and storing a new one on first use.
+pub fn secret_key() -> Result<SecretKey> {
+    if let Ok(secret) = std::env::var(SECRET_ENV) {
+        return parse(&secret);
+    }
+    let path = key_path()?;
+    let _guard = KEY_LOCK.lock().unwrap();
+    match std::fs::read_to_string(&path) {
+        Ok(secret) => parse(&secret),
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            let key = SecretKey::generate();
+            match write_key(&path, &key) {
+                Ok(()) => Ok(key),
+                // created concurrently by another instance of the app
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                    let secret =
+                        std::fs::read_to_string(&path).map_err(|err| read_err(&path, err))?;
+                    parse(&secret)
+                }
+                Err(err) => Err(write_err(&path, err)),
+            }
+        }
+        Err(err) => Err(read_err(&path, err)),
+    }
+}
+
+/// The node id of this device, as seen by peers.
+pub fn node_id() -> Result<PublicKey> {
+    Ok(secret_key()?.public())
+}
+
+/// The secret key in the format accepted by [`import`] and [`SECRET_ENV`].
+pub fn export() -> Result<String> {
+    Ok(secret_key()?.to_string())
+}
+
+/// Write the secret key to `path`, readable only by the current user.
+pub fn export_to(path: &Path) -> Result<()> {
+    let key = secret_key()?;
+    // replace, rather than truncate, a file that others may be able to read
+    std::fs::remove_file(path).ok();
+    write_key(path, &key).map_err(|err| write_err(path, err))
+}
+
+/// Replace the stored key with an exported one and return its node id.
+///
+/// A running inbox and downloads keep the key they were started with.
+pub fn import(secret: &str) -> Result<PublicKey> {
+    let key = parse(secret)?;
+    store(&key)?;
+    Ok(key.public())
+}
+
+/// Replace the stored key with a new one and return its node id.
+///
+/// Peers will no longer recognize this device. A running inbox and downloads
+/// keep the key they were started with.
+pub fn rotate() -> Result<PublicKey> {
+    let key = SecretKey::generate();
+    store(&key)?;
+    Ok(key.public())
+}
+
+/// Replace the stored key with one exported by [`export_to`] and return its
+/// node id.
+pub fn import_from(path: &Path) -> Result<PublicKey> {
+    let secret = std::fs::read_to_string(path).map_err(|err| read_err(path, err))?;
+    import(&secret)
+}
+
+fn parse(secret: &str) -> Result<SecretKey> {
+    SecretKey::from_str(secret.trim())
+        .context("invalid secret key")
+        .map_err(SendmeError::other)
+}
+
+/// Atomically replace the key file.
+fn store(key: &SecretKey) -> Result<()> {
+    let path = key_path()?;
+    let _guard = KEY_LOCK.lock().unwrap();
+    let tmp = path.with_extension("tmp");
+    std::fs::remove_file(&tmp).ok();
+    write_key(&tmp, key).map_err(|err| write_err(&tmp, err))?;
+    std::fs::rename(&tmp, &path).map_err(|err| write_err(&path, err))
+}
+
+/// Write `key` to a new file that only the current user can read.
+fn write_key(path: &Path, key: &SecretKey) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    file.write_all(key.to_string().as_bytes())?;
+    file.sync_all()
+}
+
+fn read_err(path: &Path, err: std::io::Error) -> SendmeError {
+    SendmeError::other(
+        anyhow::Error::new(err).context(format!("failed to read {}", path.display())),
+    )
+}
+
+fn write_err(path: &Path, err: std::io::Error) -> SendmeError {
+    SendmeError::other(
+        anyhow::Error::new(err).context(format!("failed to write {}", path.display())),
+    )
+}
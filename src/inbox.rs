@@ -0,0 +1,300 @@
+//! Receiving collections that are pushed to this device.
+//!
+//! An [`Inbox`] listens on [`INBOX_ALPN`]. A sender shares its files as usual
+//! and then offers the ticket of the share to the inbox with [`Share::offer`].
+//! If the user accepts the [`Offer`], the receiver downloads the ticket like
+//! any other, e.g. with [`Download::start`](crate::Download::start).
+use std::{
+    fmt,
+    future::Future,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::Context;
+use iroh_net::{
+    key::{PublicKey, Signature},
+    magic_endpoint::get_remote_node_id,
+    ticket::BlobTicket,
+    MagicEndpoint, NodeAddr,
+};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use crate::{
+    endpoint::{self, RelayMode},
+    error::{Result, SendmeError},
+    identity, parse_ticket, Share,
+};
+
+/// ALPN of the inbox protocol.
+pub const INBOX_ALPN: &[u8] = b"sendme/inbox/1";
+
+/// Prefix of the string form of an [`InboxTicket`].
+const INBOX_TICKET_PREFIX: &str = "sendmeinbox";
+
+/// Offers and replies are small, anything larger is not an offer.
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+/// The address of an inbox, for senders that can not find it nearby.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InboxTicket(pub NodeAddr);
+
+impl fmt::Display for InboxTicket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = serde_json::to_vec(&self.0).map_err(|_| fmt::Error)?;
+        let data = data_encoding::BASE32_NOPAD
+            .encode(&bytes)
+            .to_ascii_lowercase();
+        write!(f, "{}{}", INBOX_TICKET_PREFIX, data)
+    }
+}
+
+impl FromStr for InboxTicket {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let data = s
+            .trim()
+            .strip_prefix(INBOX_TICKET_PREFIX)
+            .context("not an inbox ticket")?;
+        let bytes = data_encoding::BASE32_NOPAD.decode(data.to_ascii_uppercase().as_bytes())?;
+        Ok(Self(serde_json::from_slice(&bytes)?))
+    }
+}
+
+/// Sent by the sender: the share it wants to push.
+#[derive(Debug, Serialize, Deserialize)]
+struct OfferRequest {
+    /// Device name of the sender.
+    name: String,
+    /// Node id of the sender. The offer comes from the endpoint of the share,
+    /// which has a key of its own.
+    device: PublicKey,
+    /// Signature of the device over the node id of the share, see
+    /// [`signed_message`].
+    signature: Vec<u8>,
+    ticket: String,
+    files: u64,
+    size: u64,
+}
+
+/// Sent by the inbox once the user decided.
+#[derive(Debug, Serialize, Deserialize)]
+struct OfferReply {
+    accepted: bool,
+}
+
+/// What a sender signs to show that a share is from its device.
+fn signed_message(share: &PublicKey) -> Vec<u8> {
+    [b"sendme offer from ".as_slice(), share.as_bytes().as_slice()].concat()
+}
+
+/// A collection pushed to an [`Inbox`], waiting for the user to decide.
+///
+/// Dropping the offer rejects it.
+#[derive(Debug)]
+pub struct Offer {
+    /// Node id of the sending device.
+    pub from: PublicKey,
+    /// Device name of the sender.
+    pub name: String,
+    /// Ticket of the share of the sender.
+    pub ticket: BlobTicket,
+    pub files: u64,
+    pub size: u64,
+    reply: flume::Sender<bool>,
+}
+
+impl Offer {
+    /// Tell the sender the collection will be downloaded.
+    pub fn accept(self) {
+        self.reply.send(true).ok();
+    }
+
+    /// Tell the sender the collection will not be downloaded.
+    pub fn reject(self) {
+        self.reply.send(false).ok();
+    }
+}
+
+/// Listens for offers from other devices, created by [`Inbox::start`].
+///
+/// An inbox starts closed and rejects all offers until it is opened.
+#[derive(Debug)]
+pub struct Inbox {
+    endpoint: MagicEndpoint,
+    open: Arc<AtomicBool>,
+    /// Cancelled to stop listening and drop the offers that are waiting.
+    stopped: CancellationToken,
+    task: JoinHandle<()>,
+}
+
+impl Inbox {
+    /// Start listening and send offers to `offers` while the inbox is open.
+    pub async fn start(relay: RelayMode, offers: flume::Sender<Offer>) -> Result<Self> {
+        let endpoint = endpoint::device(&relay).await?;
+        let open = Arc::new(AtomicBool::new(false));
+        let stopped = CancellationToken::new();
+        let task = tokio::spawn({
+            let endpoint = endpoint.clone();
+            let open = open.clone();
+            let stopped = stopped.clone();
+            async move {
+                loop {
+                    let connecting = tokio::select! {
+                        connecting = endpoint.accept() => match connecting {
+                            Some(connecting) => connecting,
+                            None => break,
+                        },
+                        _ = stopped.cancelled() => break,
+                    };
+                    let open = open.clone();
+                    let offers = offers.clone();
+                    let stopped = stopped.clone();
+                    tokio::spawn(async move {
+                        tokio::select! {
+                            res = handle_offer(connecting, open, offers) => {
+                                if let Err(err) = res {
+                                    warn!("failed to receive offer: {:?}", err);
+                                }
+                            }
+                            _ = stopped.cancelled() => {}
+                        }
+                    });
+                }
+            }
+        });
+        Ok(Self {
+            endpoint,
+            open,
+            stopped,
+            task,
+        })
+    }
+
+    /// Accept or reject new offers.
+    pub fn set_open(&self, open: bool) {
+        self.open.store(open, Ordering::Relaxed);
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn open_flag(&self) -> Arc<AtomicBool> {
+        self.open.clone()
+    }
+
+    /// The endpoint the inbox listens on.
+    pub fn endpoint(&self) -> &MagicEndpoint {
+        &self.endpoint
+    }
+
+    /// A ticket to give to senders.
+    pub async fn ticket(&self, relay: &RelayMode) -> Result<InboxTicket> {
+        let addr = endpoint::node_addr(&self.endpoint, relay).await?;
+        Ok(InboxTicket(addr))
+    }
+
+    /// Stop listening. Offers that are still waiting are rejected.
+    ///
+    /// The endpoint stays open for the downloads of this device, which share
+    /// it.
+    pub async fn stop(self) {
+        self.stopped.cancel();
+        self.task.await.ok();
+    }
+}
+
+async fn handle_offer(
+    connecting: quinn::Connecting,
+    open: Arc<AtomicBool>,
+    offers: flume::Sender<Offer>,
+) -> anyhow::Result<()> {
+    let connection = connecting.await?;
+    let from = get_remote_node_id(&connection)?;
+    let (mut send, mut recv) = connection.accept_bi().await?;
+    let request = recv.read_to_end(MAX_MESSAGE_SIZE).await?;
+    let request: OfferRequest = serde_json::from_slice(&request)?;
+    let ticket = parse_ticket(&request.ticket)?;
+    anyhow::ensure!(
+        ticket.node_addr().node_id == from,
+        "offer from {} for a ticket of {}",
+        from,
+        ticket.node_addr().node_id
+    );
+    let signature = Signature::from_slice(&request.signature)?;
+    request
+        .device
+        .verify(&signed_message(&from), &signature)
+        .context("offer is not signed by the sending device")?;
+    let from = request.device;
+    debug!("offer from {}: {}", from, ticket);
+
+    let accepted = if open.load(Ordering::Relaxed) {
+        let (reply, decision) = flume::bounded(1);
+        let offer = Offer {
+            from,
+            name: request.name,
+            ticket,
+            files: request.files,
+            size: request.size,
+            reply,
+        };
+        offers.send_async(offer).await.is_ok() && decision.recv_async().await.unwrap_or(false)
+    } else {
+        false
+    };
+    info!("offer from {} accepted: {}", from, accepted);
+    send.write_all(&serde_json::to_vec(&OfferReply { accepted })?)
+        .await?;
+    send.finish().await?;
+    Ok(())
+}
+
+/// Offer `share` to the inbox at `to`, see [`Share::offer`].
+pub(crate) fn send_offer(
+    share: &Share,
+    endpoint: MagicEndpoint,
+    to: NodeAddr,
+    name: String,
+) -> impl Future<Output = Result<bool>> + Send + 'static {
+    let ticket = share.ticket.to_string();
+    let files = share.collection.len() as u64;
+    let size = share.size;
+    async move {
+        let secret_key = identity::secret_key()?;
+        let request = OfferRequest {
+            name,
+            device: secret_key.public(),
+            signature: secret_key
+                .sign(&signed_message(&endpoint.node_id()))
+                .to_bytes()
+                .to_vec(),
+            ticket,
+            files,
+            size,
+        };
+        let connection = endpoint
+            .connect(to, INBOX_ALPN)
+            .await
+            .map_err(SendmeError::PeerUnreachable)?;
+        let res = async {
+            let (mut send, mut recv) = connection.open_bi().await?;
+            send.write_all(&serde_json::to_vec(&request)?).await?;
+            send.finish().await?;
+            let reply = recv.read_to_end(MAX_MESSAGE_SIZE).await?;
+            let reply: OfferReply = serde_json::from_slice(&reply)?;
+            anyhow::Ok(reply.accepted)
+        }
+        .await;
+        connection.close(0u32.into(), b"offer done");
+        res.map_err(SendmeError::other)
+    }
+}
@@ -0,0 +1,53 @@
+//! Send files and directories between devices, using iroh.
+//!
+//! Files are imported into a collection and served with [`provide`], which
+//! returns a [`Share`] handle. The receiver fetches them with the ticket of the
+//! share, either with [`get`] or in the background with a [`Download`] handle.
+//! [`preview`] lists the files of a ticket without downloading them.
+//!
+//! Both sides use the node id kept by [`identity`]. [`Discovery`] finds other
+//! devices running the app on the local network, and an [`Inbox`] receives
+//! collections that other devices push to it. With a [`rendezvous`], tickets
+//! can be exchanged as short codes that are easy to read out.
+use std::path::PathBuf;
+
+use anyhow::Context;
+use directories::ProjectDirs;
+
+pub mod allowlist;
+pub mod collection;
+pub mod discovery;
+mod endpoint;
+mod error;
+pub mod get;
+pub mod identity;
+pub mod inbox;
+pub mod progress;
+pub mod provide;
+pub mod rendezvous;
+
+pub use allowlist::Allowlist;
+pub use collection::{canonicalized_path_to_string, path_stats, print_hash, Format, PathStats};
+pub use discovery::{Discovery, NearbyDevice};
+pub use endpoint::RelayMode;
+pub use error::{Result, SendmeError};
+pub use get::{get, parse_ticket, preview, Download, FileInfo, GetProgress, GetStats, Preview};
+pub use inbox::{Inbox, InboxTicket, Offer};
+pub use provide::{cleanup_provide_stores, provide, Share, SharePolicy};
+pub use rendezvous::{Rendezvous, ShortCode};
+
+/// Directory for data owned by the app, such as the stores of active shares.
+pub fn data_dir() -> Result<PathBuf> {
+    let dirs = ProjectDirs::from("computer", "n0", "Sendme")
+        .context("no home directory")
+        .map_err(SendmeError::other)?;
+    Ok(dirs.data_local_dir().to_path_buf())
+}
+
+/// Directory for settings of the app, such as the secret key.
+pub fn config_dir() -> Result<PathBuf> {
+    let dirs = ProjectDirs::from("computer", "n0", "Sendme")
+        .context("no home directory")
+        .map_err(SendmeError::other)?;
+    Ok(dirs.config_dir().to_path_buf())
+}
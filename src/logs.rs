@@ -0,0 +1,157 @@
+//! Logging to a rotating file in the app data dir, to stderr, and to an in
+//! memory buffer that backs the log panel.
+use std::{
+    collections::VecDeque,
+    fmt::{self, Write},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use tracing::{field::Field, Event, Level, Subscriber};
+use tracing_appender::{non_blocking::WorkerGuard, rolling};
+use tracing_subscriber::{
+    field::Visit,
+    filter::{EnvFilter, LevelFilter, Targets},
+    fmt::layer,
+    layer::{Context, SubscriberExt},
+    util::SubscriberInitExt,
+    Layer,
+};
+
+/// Number of lines kept for the log panel.
+const MAX_LINES: usize = 2000;
+
+/// Number of daily log files kept in the app data dir.
+const MAX_LOG_FILES: usize = 7;
+
+/// A single log message.
+#[derive(Debug, Clone)]
+pub struct LogLine {
+    /// Time since logging was set up.
+    pub elapsed: Duration,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+impl fmt::Display for LogLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>9.3}s {:>5} {}: {}",
+            self.elapsed.as_secs_f64(),
+            self.level,
+            self.target,
+            self.message
+        )
+    }
+}
+
+/// The most recent log lines, shared with the log panel.
+#[derive(Debug, Clone, Default)]
+pub struct LogBuffer(Arc<Mutex<VecDeque<LogLine>>>);
+
+impl LogBuffer {
+    pub fn lines(&self) -> MutexGuard<'_, VecDeque<LogLine>> {
+        self.0.lock().unwrap()
+    }
+}
+
+/// Set up logging.
+///
+/// Messages of this app and of its dependencies at info level and above go to
+/// a daily log file and to `buffer`. Warnings go to stderr, which `RUST_LOG`
+/// can override.
+///
+/// The returned guard flushes the log file when dropped, so it must be kept
+/// alive until the process exits.
+pub fn init(buffer: LogBuffer) -> Option<WorkerGuard> {
+    let targets = Targets::new()
+        .with_target("sendme", Level::DEBUG)
+        .with_default(Level::INFO);
+
+    let appender = sendme::data_dir()
+        .map_err(anyhow::Error::new)
+        .and_then(|dir| {
+            rolling::Builder::new()
+                .rotation(rolling::Rotation::DAILY)
+                .filename_prefix("sendme")
+                .filename_suffix("log")
+                .max_log_files(MAX_LOG_FILES)
+                .build(dir.join("logs"))
+                .map_err(anyhow::Error::new)
+        });
+    let (file, guard, file_err) = match appender {
+        Ok(appender) => {
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            let file = layer()
+                .with_ansi(false)
+                .with_writer(writer)
+                .with_filter(targets.clone());
+            (Some(file), Some(guard), None)
+        }
+        Err(err) => (None, None, Some(err)),
+    };
+
+    let stderr = layer().with_writer(std::io::stderr).with_filter(
+        EnvFilter::builder()
+            .with_default_directive(LevelFilter::WARN.into())
+            .from_env_lossy(),
+    );
+    let buffer = BufferLayer {
+        buffer,
+        started: Instant::now(),
+    }
+    .with_filter(targets);
+
+    tracing_subscriber::registry()
+        .with(file)
+        .with(stderr)
+        .with(buffer)
+        .init();
+    if let Some(err) = file_err {
+        tracing::warn!("failed to open the log file: {:?}", err);
+    }
+    guard
+}
+
+/// Records events in a [`LogBuffer`].
+struct BufferLayer {
+    buffer: LogBuffer,
+    started: Instant,
+}
+
+impl<S: Subscriber> Layer<S> for BufferLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        let line = LogLine {
+            elapsed: self.started.elapsed(),
+            level: *event.metadata().level(),
+            target: event.metadata().target().to_string(),
+            message: visitor.0,
+        };
+        let mut lines = self.buffer.lines();
+        if lines.len() == MAX_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+}
+
+/// Formats the message of an event, followed by its other fields.
+#[derive(Default)]
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        if field.name() == "message" {
+            write!(self.0, "{:?}", value).ok();
+        } else {
+            write!(self.0, "{}={:?}", field.name(), value).ok();
+        }
+    }
+}
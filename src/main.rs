@@ -1,24 +1,58 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 use std::{
-    path::PathBuf,
+    collections::HashMap,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
+    time::Duration,
 };
 
 use anyhow::Context;
+use clap::Parser;
 use eframe::{
     egui::{self, Button, RichText, Style},
     emath::Align,
     epaint::{vec2, Color32, Stroke},
 };
-use iroh_bytes::get::db::DownloadProgress;
-use iroh_net::ticket::BlobTicket;
+use iroh_net::key::PublicKey;
+use sendme::{progress::TransferProgress, Allowlist, SendmeError, SharePolicy};
+use tracing::{warn, Level};
+use tree::FileTree;
+use worker::{ActiveShare, Job, JobKind, PeerProgress, PeerStatus, SharedState, WorkerMessage};
 
-mod upload;
+mod cli;
+mod config;
+mod logs;
+mod qr;
+mod settings;
+mod tree;
+mod worker;
 
 const HEIGHT: f32 = 480.;
 
 fn main() -> Result<(), eframe::Error> {
+    // release builds on windows have no console of their own, so the command
+    // line, including --help, prints to the console it was started from
+    if std::env::args_os().len() > 1 {
+        attach_console();
+    }
+    let args = cli::Args::parse();
+    let logs = logs::LogBuffer::default();
+    let log_guard = logs::init(logs.clone());
+    if let Err(err) = sendme::cleanup_provide_stores() {
+        warn!("failed to clean up stores: {:?}", err);
+    }
+    let config = config::Config::load().unwrap_or_else(|err| {
+        warn!("failed to load settings, using the defaults: {:?}", err);
+        Default::default()
+    });
+    if args.command.is_some() {
+        let code = cli::run(args, config);
+        // exiting skips destructors, flush the log file first
+        drop(log_guard);
+        std::process::exit(code);
+    }
+
     let mut viewport = egui::ViewportBuilder::default()
         .with_inner_size([640.0, HEIGHT])
         .with_drag_and_drop(true);
@@ -51,36 +85,33 @@ fn main() -> Result<(), eframe::Error> {
         viewport,
         ..Default::default()
     };
-    eframe::run_native("Sendme", options, Box::new(|cc| Box::new(Sapp::new(cc))))
+    eframe::run_native(
+        "Sendme",
+        options,
+        Box::new(|cc| Box::new(Sapp::new(cc, logs, config))),
+    )
 }
 
 struct Sapp {
-    selected_file: Option<PathBuf>,
+    selected_files: Vec<PathBuf>,
     input_text: String,
     download_target: Option<PathBuf>,
     shared_state: Arc<Mutex<SharedState>>,
     worker: flume::Sender<WorkerMessage>,
-}
-
-#[derive(Debug, Default)]
-struct SharedState {
-    sharing_progress: Option<f32>,
-    ticket: Option<BlobTicket>,
-    download_progress: Option<f32>,
-    errors: Vec<anyhow::Error>,
-}
-
-impl SharedState {
-    fn reset_download(&mut self) {
-        self.sharing_progress = None;
-        self.ticket = None;
-    }
-}
-
-#[derive(Debug)]
-enum WorkerMessage {
-    Share(PathBuf),
-    Get(String, PathBuf),
+    logs: logs::LogBuffer,
+    /// The most verbose level shown in the log panel.
+    log_level: Level,
+    settings: settings::Settings,
+    /// Inbox ticket of a device to send the selected files to.
+    inbox_ticket_input: String,
+    /// QR codes of the tickets of the active shares.
+    qr_codes: HashMap<worker::JobId, egui::TextureHandle>,
+    /// New shares only serve the receivers that are allowed explicitly.
+    restrict_shares: bool,
+    /// Policy of new shares.
+    share_policy: SharePolicy,
+    /// Node id to allow, as entered for each active share.
+    allow_inputs: HashMap<worker::JobId, String>,
 }
 
 const DARK_BG: Color32 = Color32::from_rgb(26, 28, 32);
@@ -89,8 +120,29 @@ const TEXT_COLOR: Color32 = Color32::from_rgb(117, 121, 131);
 const BLUE_COLOR: Color32 = Color32::from_rgb(0, 138, 216);
 const WHITE_COLOR: Color32 = Color32::WHITE;
 
+/// How long new shares can be kept up.
+const EXPIRY_CHOICES: [(Option<Duration>, &str); 5] = [
+    (None, "until stopped"),
+    (Some(Duration::from_secs(10 * 60)), "10 minutes"),
+    (Some(Duration::from_secs(60 * 60)), "1 hour"),
+    (Some(Duration::from_secs(24 * 60 * 60)), "1 day"),
+    (Some(Duration::from_secs(7 * 24 * 60 * 60)), "7 days"),
+];
+
+/// How many receivers new shares can serve.
+const DOWNLOAD_CHOICES: [(Option<u64>, &str); 4] = [
+    (None, "any number of receivers"),
+    (Some(1), "the first receiver"),
+    (Some(3), "3 receivers"),
+    (Some(10), "10 receivers"),
+];
+
 impl Sapp {
-    fn new(cc: &eframe::CreationContext<'_>) -> Self {
+    fn new(
+        cc: &eframe::CreationContext<'_>,
+        logs: logs::LogBuffer,
+        config: config::Config,
+    ) -> Self {
         // Configure basic style
 
         let mut style = Style::default();
@@ -132,133 +184,327 @@ impl Sapp {
 
         cc.egui_ctx.set_style(style);
 
-        let ctx = cc.egui_ctx.clone();
-        let shared_state = Arc::new(Mutex::new(SharedState::default()));
-        let ss1 = shared_state.clone();
-        let (s, r) = flume::unbounded();
+        let rendezvous = config.rendezvous().unwrap_or_else(|err| {
+            warn!("invalid rendezvous, not using short codes: {:?}", err);
+            None
+        });
+        let shared_state = Arc::new(Mutex::new(SharedState {
+            relay: config.relay.clone(),
+            device_name: config.device_name(),
+            hidden: config.hidden,
+            rendezvous,
+            ..Default::default()
+        }));
+        let worker = worker::spawn(cc.egui_ctx.clone(), shared_state.clone());
 
+        Sapp {
+            shared_state,
+            input_text: String::new(),
+            download_target: None,
+            worker,
+            selected_files: Vec::new(),
+            logs,
+            log_level: Level::INFO,
+            settings: settings::Settings::new(config),
+            inbox_ticket_input: String::new(),
+            qr_codes: HashMap::new(),
+            restrict_shares: false,
+            share_policy: SharePolicy::default(),
+            allow_inputs: HashMap::new(),
+        }
+    }
+
+    /// The allowlist of a new share.
+    fn new_allowlist(&self) -> Allowlist {
+        if self.restrict_shares {
+            Allowlist::only([])
+        } else {
+            Allowlist::anyone()
+        }
+    }
+
+    /// Let the user choose when new shares stop by themselves.
+    fn show_share_policy(&mut self, ui: &mut egui::Ui) {
+        let policy = &mut self.share_policy;
+        ui.horizontal(|ui| {
+            ui.label("Keep up");
+            let selected = EXPIRY_CHOICES
+                .iter()
+                .find(|(value, _)| *value == policy.expires_after)
+                .map_or("", |(_, label)| *label);
+            egui::ComboBox::from_id_source("expires_after")
+                .selected_text(selected)
+                .show_ui(ui, |ui| {
+                    for (value, label) in EXPIRY_CHOICES {
+                        ui.selectable_value(&mut policy.expires_after, value, label);
+                    }
+                });
+        });
+        ui.horizontal(|ui| {
+            ui.label("Serve");
+            let selected = DOWNLOAD_CHOICES
+                .iter()
+                .find(|(value, _)| *value == policy.max_downloads)
+                .map_or("", |(_, label)| *label);
+            egui::ComboBox::from_id_source("max_downloads")
+                .selected_text(selected)
+                .show_ui(ui, |ui| {
+                    for (value, label) in DOWNLOAD_CHOICES {
+                        ui.selectable_value(&mut policy.max_downloads, value, label);
+                    }
+                });
+        });
+    }
+
+    /// Add paths to the selection, skipping the ones that are already in it.
+    ///
+    /// New paths are scanned in the background to show their size.
+    fn add_files(&mut self, ctx: &egui::Context, paths: impl IntoIterator<Item = PathBuf>) {
+        for path in paths {
+            if !self.selected_files.contains(&path) {
+                self.scan_path(ctx, path.clone());
+                self.selected_files.push(path);
+            }
+        }
+    }
+
+    fn scan_path(&self, ctx: &egui::Context, path: PathBuf) {
+        if self
+            .shared_state
+            .lock()
+            .unwrap()
+            .path_stats
+            .contains_key(&path)
+        {
+            return;
+        }
+        let ctx = ctx.clone();
+        let shared_state = self.shared_state.clone();
         std::thread::spawn(move || {
-            let rt = tokio::runtime::Runtime::new().unwrap();
-
-            while let Ok(msg) = r.recv() {
-                match msg {
-                    WorkerMessage::Share(path) => {
-                        println!("sharing: {}", path.display());
-
-                        // import progress
-                        let (send, recv) = flume::bounded(32);
-
-                        let ctx2 = ctx.clone();
-                        let ss2 = ss1.clone();
-                        let res =
-                            rt.block_on(async move {
-                                tokio::task::spawn(async move {
-                                    let mut total_size = 0;
-                                    let mut imported_size = 0;
-                                    while let Ok(ev) = recv.recv_async().await {
-                                        match ev {
-                                        iroh_bytes::store::ImportProgress::Size { size, .. } => {
-                                            total_size += size;
-                                            let p = total_size as f32 / imported_size as f32;
-                                            ss2.lock().unwrap().sharing_progress.replace(p);
-                                            ctx2.request_repaint();
-                                        }
-                                        iroh_bytes::store::ImportProgress::OutboardProgress {
-                                            offset,
-                                            ..
-                                        } => {
-                                            imported_size += offset;
-                                            let p = total_size as f32 / imported_size as f32;
-                                            ss2.lock().unwrap().sharing_progress.replace(p);
-                                            ctx2.request_repaint();
-                                        }
-                                        _ => {}
-                                    }
-                                    }
-                                });
-                                let (ticket, _handle) = upload::provide(path, send).await?;
-                                anyhow::Ok(ticket)
-                            });
-                        match res {
-                            Ok(ticket) => {
-                                let mut state = ss1.lock().unwrap();
-                                state.sharing_progress = None;
-                                state.ticket = Some(ticket);
+            let stats = match sendme::path_stats(&path) {
+                Ok(stats) => Some(stats),
+                Err(err) => {
+                    warn!("failed to scan {}: {:?}", path.display(), err);
+                    None
+                }
+            };
+            shared_state.lock().unwrap().path_stats.insert(path, stats);
+            ctx.request_repaint();
+        });
+    }
 
-                                ctx.request_repaint();
-                            }
-                            Err(err) => {
-                                eprintln!("failed: {:?}", err);
-                                ss1.lock().unwrap().errors.push(err.context("sharing"));
-                            }
-                        }
+    /// Show the devices found on the local network and the inbox.
+    fn show_nearby(&mut self, ctx: &egui::Context) {
+        let mut state = self.shared_state.lock().unwrap();
+        // where to push the selected files to
+        let mut push = None;
+        egui::SidePanel::right("nearby").show(ctx, |ui| {
+            ui.add_space(10.);
+            ui.label(RichText::new("Nearby").heading().color(WHITE_COLOR));
+            let can_send = !self.selected_files.is_empty();
+            if state.nearby.is_empty() {
+                ui.label("No devices found");
+            }
+            for device in &state.nearby {
+                ui.add_space(5.);
+                ui.label(RichText::new(&device.name).color(WHITE_COLOR))
+                    .on_hover_text(device.addr.node_id.to_string());
+                ui.horizontal(|ui| {
+                    ui.label(device.addr.node_id.fmt_short());
+                    if device.inbox
+                        && ui
+                            .add_enabled(can_send, Button::new("Send"))
+                            .on_disabled_hover_text("Select files to send first")
+                            .clicked()
+                    {
+                        push = Some((device.addr.clone(), device.name.clone()));
                     }
-                    WorkerMessage::Get(ticket, target) => {
-                        match ticket.parse::<BlobTicket>() {
-                            Ok(ticket) => {
-                                println!("getting: {}", ticket);
-                                // import progress
-                                let (send, recv) = flume::bounded(32);
-
-                                let ctx2 = ctx.clone();
-                                let ss2 = ss1.clone();
-                                ss1.lock().unwrap().download_progress = Some(0.0);
-                                ctx.request_repaint();
-
-                                let res = rt.block_on(async move {
-                                    tokio::task::spawn(async move {
-                                        while let Ok(ev) = recv.recv_async().await {
-                                            // TODO: propper progress
-                                            match ev {
-                                                DownloadProgress::AllDone => {}
-                                                _ => {}
-                                            }
-                                        }
-                                    });
+                });
+            }
 
-                                    upload::get(ticket, target, send).await
-                                });
+            ui.add_space(10.);
+            ui.label("Inbox ticket of another device:");
+            ui.text_edit_singleline(&mut self.inbox_ticket_input);
+            let enabled = can_send && !self.inbox_ticket_input.trim().is_empty();
+            if ui.add_enabled(enabled, Button::new("Send")).clicked() {
+                match self.inbox_ticket_input.parse::<sendme::InboxTicket>() {
+                    Ok(ticket) => {
+                        let name = ticket.0.node_id.fmt_short();
+                        push = Some((ticket.0, name));
+                        self.inbox_ticket_input.clear();
+                    }
+                    Err(err) => state.errors.push(err.context("invalid inbox ticket")),
+                }
+            }
 
-                                if let Err(err) = res {
-                                    eprintln!("failed: {:?}", err);
-                                    ss1.lock().unwrap().errors.push(err.context("get"));
-                                } else {
-                                    ss1.lock().unwrap().download_progress = None;
-                                    ctx.request_repaint();
-                                }
-                            }
-                            Err(err) => {
-                                eprintln!("invalid ticket: {:?}", err);
-                                ss1.lock()
-                                    .unwrap()
-                                    .errors
-                                    .push(anyhow::anyhow!(err).context("parsing ticket"));
-                            }
-                        }
+            ui.add_space(20.);
+            ui.label(RichText::new("Inbox").heading().color(WHITE_COLOR));
+            match &state.inbox {
+                Some(inbox) => {
+                    let mut open = inbox.is_open();
+                    if ui.checkbox(&mut open, "Accept files from others").changed() {
+                        inbox.set_open(open);
                     }
+                    if let Some(ticket) = state.inbox_ticket.as_ref().filter(|_| open) {
+                        ui.add_space(5.);
+                        ui.label("Inbox ticket for devices that are not nearby:");
+                        // selectable text
+                        let ticket_text = ticket.to_string();
+                        let mut text: &str = &ticket_text;
+                        ui.add(
+                            egui::TextEdit::multiline(&mut text).font(egui::FontId::monospace(12.)),
+                        );
+                    }
+                }
+                None => {
+                    ui.spinner();
                 }
             }
         });
+        drop(state);
 
-        Sapp {
-            shared_state,
-            input_text: String::new(),
-            download_target: None,
-            worker: s,
-            selected_file: None,
+        if let Some((addr, name)) = push {
+            let paths = std::mem::take(&mut self.selected_files);
+            // the inbox downloads with the node id it listens on
+            let allowlist = self.new_allowlist();
+            if allowlist.is_restricted() {
+                allowlist.allow(addr.node_id);
+            }
+            self.worker
+                .send(WorkerMessage::Push(
+                    paths,
+                    allowlist,
+                    self.share_policy,
+                    addr,
+                    name,
+                ))
+                .ok();
+        }
+    }
+
+    /// Ask the user about the oldest offer of another device.
+    fn show_offers(&mut self, ctx: &egui::Context) {
+        let state = self.shared_state.lock().unwrap();
+        let Some(offer) = state.offers.first() else {
+            return;
+        };
+        let mut decision = None;
+        egui::Window::new("Incoming files")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0., 0.])
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "{} wants to send you {} files, {}.",
+                    offer.name,
+                    offer.files,
+                    format_size(offer.size)
+                ));
+                ui.label(
+                    RichText::new(offer.from.to_string())
+                        .monospace()
+                        .color(TEXT_COLOR),
+                );
+                ui.add_space(5.);
+                if let Some(ref target) = self.download_target {
+                    ui.label(format!("Target: {}", target.display()));
+                }
+                ui.horizontal(|ui| {
+                    let accept = match self.download_target {
+                        Some(_) => "Accept",
+                        None => "Accept…",
+                    };
+                    if ui.button(accept).clicked() {
+                        decision = Some(true);
+                    }
+                    if ui.button("Reject").clicked() {
+                        decision = Some(false);
+                    }
+                });
+            });
+        // no lock while the folder dialog is open
+        drop(state);
+
+        match decision {
+            Some(true) => {
+                let target = self
+                    .download_target
+                    .clone()
+                    .or_else(|| rfd::FileDialog::new().pick_folder());
+                if let Some(target) = target {
+                    let offer = self.shared_state.lock().unwrap().offers.remove(0);
+                    self.worker
+                        .send(WorkerMessage::Get(offer.ticket.clone(), target, None))
+                        .ok();
+                    offer.accept();
+                }
+            }
+            Some(false) => self.shared_state.lock().unwrap().offers.remove(0).reject(),
+            None => {}
         }
     }
 
+    /// Show the collapsible log panel at the bottom of the window.
+    fn show_logs(&mut self, ctx: &egui::Context) {
+        egui::TopBottomPanel::bottom("logs").show(ctx, |ui| {
+            egui::CollapsingHeader::new("Logs").show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_source("log_level")
+                        .selected_text(self.log_level.as_str())
+                        .show_ui(ui, |ui| {
+                            for level in [Level::ERROR, Level::WARN, Level::INFO, Level::DEBUG] {
+                                ui.selectable_value(&mut self.log_level, level, level.as_str());
+                            }
+                        });
+                    if ui.button("Copy logs").clicked() {
+                        // all of them, regardless of the level shown
+                        let text = self
+                            .logs
+                            .lines()
+                            .iter()
+                            .map(|line| line.to_string())
+                            .collect::<Vec<_>>()
+                            .join("\n");
+                        ui.output_mut(|o| o.copied_text = text);
+                    }
+                });
+
+                // copied out, so the buffer is not locked while rendering,
+                // which may log itself
+                let shown = self
+                    .logs
+                    .lines()
+                    .iter()
+                    .filter(|line| line.level <= self.log_level)
+                    .map(|line| (line.level, line.to_string()))
+                    .collect::<Vec<_>>();
+                let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+                egui::ScrollArea::vertical()
+                    .max_height(150.)
+                    .stick_to_bottom(true)
+                    .show_rows(ui, row_height, shown.len(), |ui, rows| {
+                        for (level, line) in &shown[rows] {
+                            let color = match *level {
+                                Level::ERROR => Color32::LIGHT_RED,
+                                Level::WARN => Color32::LIGHT_YELLOW,
+                                _ => TEXT_COLOR,
+                            };
+                            ui.label(RichText::new(line).monospace().color(color));
+                        }
+                    });
+                // pick up new lines
+                ctx.request_repaint_after(Duration::from_secs(1));
+            });
+        });
+    }
+
     fn show_errors(&mut self, ctx: &egui::Context) {
-        let err = self
-            .shared_state
-            .lock()
-            .unwrap()
-            .errors
-            .last()
-            .map(|e| format!("{:#}", e));
+        let err = self.shared_state.lock().unwrap().errors.last().map(|e| {
+            let help = e.downcast_ref::<SendmeError>().map(error_help);
+            (format!("{:#}", e), help.unwrap_or_default())
+        });
 
-        if let Some(err) = err {
+        if let Some((err, help)) = err {
             let center = ctx.screen_rect().center();
             let width = 200.;
             let height = 100.;
@@ -267,7 +513,8 @@ impl Sapp {
                 x: center.x - width / 2.,
                 y: center.y - height / 2.,
             };
-            egui::Window::new("⚠ Error")
+            egui::Window::new(format!("⚠ {}", help.title))
+                .id(egui::Id::new("error"))
                 .fixed_size(vec2(width, height))
                 .collapsible(false)
                 .fixed_pos(pos)
@@ -277,12 +524,35 @@ impl Sapp {
 
                     ui.add_space(5.);
                     ui.label(err);
+                    if let Some(hint) = help.hint {
+                        ui.add_space(5.);
+                        ui.label(RichText::new(hint).color(WHITE_COLOR));
+                    }
                     ui.add_space(5.);
 
                     ui.with_layout(egui::Layout::bottom_up(Align::RIGHT), |ui| {
-                        if ui.button("Ok").clicked() {
-                            self.shared_state.lock().unwrap().errors.pop();
-                        }
+                        ui.horizontal(|ui| {
+                            if ui.button("Ok").clicked() {
+                                self.shared_state.lock().unwrap().errors.pop();
+                            }
+                            match help.action {
+                                Some(ErrorAction::ClearTicket) => {
+                                    if ui.button("Clear ticket").clicked() {
+                                        self.input_text.clear();
+                                        self.shared_state.lock().unwrap().errors.pop();
+                                    }
+                                }
+                                Some(ErrorAction::ChooseFolder) => {
+                                    if ui.button("Choose folder…").clicked() {
+                                        if let Some(path) = rfd::FileDialog::new().pick_folder() {
+                                            self.download_target.replace(path);
+                                        }
+                                        self.shared_state.lock().unwrap().errors.pop();
+                                    }
+                                }
+                                None => {}
+                            }
+                        });
                         ui.add_space(5.);
                         ui.separator();
                     });
@@ -291,50 +561,216 @@ impl Sapp {
     }
 }
 
+/// How an error is presented to the user.
+struct ErrorHelp {
+    title: &'static str,
+    /// What the user can do about the error.
+    hint: Option<&'static str>,
+    action: Option<ErrorAction>,
+}
+
+impl Default for ErrorHelp {
+    fn default() -> Self {
+        Self {
+            title: "Error",
+            hint: None,
+            action: None,
+        }
+    }
+}
+
+/// A button offered next to an error to recover from it.
+#[derive(Debug, Clone, Copy)]
+enum ErrorAction {
+    ClearTicket,
+    ChooseFolder,
+}
+
+fn error_help(err: &SendmeError) -> ErrorHelp {
+    let (title, hint, action) = match err {
+        SendmeError::InvalidTicket(_) => (
+            "Invalid ticket",
+            "Check that the whole ticket was copied, or that the code was typed correctly and is still shared.",
+            Some(ErrorAction::ClearTicket),
+        ),
+        SendmeError::PeerUnreachable(_) => (
+            "Sender unreachable",
+            "Make sure the sender is still sharing and both devices are online, then try again.",
+            None,
+        ),
+        SendmeError::PathRejected { .. } => (
+            "Path rejected",
+            "Remove the path from the selection, or rename it.",
+            None,
+        ),
+        SendmeError::DiskFull(_) => (
+            "Disk full",
+            "Free up some space, then download again into the same folder. Data that was already received is kept there.",
+            None,
+        ),
+        SendmeError::TargetExists(_) => (
+            "File exists",
+            "Move the existing file away or choose another folder, then download again.",
+            Some(ErrorAction::ChooseFolder),
+        ),
+        SendmeError::StoreIo(_) => (
+            "Storage error",
+            "Check that the folder is writable and try again.",
+            None,
+        ),
+        SendmeError::Cancelled | SendmeError::Other(_) => return ErrorHelp::default(),
+    };
+    ErrorHelp {
+        title,
+        hint: Some(hint),
+        action,
+    }
+}
+
 impl eframe::App for Sapp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        egui::TopBottomPanel::top("menu").show(ctx, |ui| {
+            ui.with_layout(egui::Layout::right_to_left(Align::Center), |ui| {
+                if ui.button("Settings").clicked() {
+                    self.settings.open();
+                }
+            });
+        });
+        self.settings.show(ctx, &self.shared_state);
+        self.show_logs(ctx);
+        self.show_nearby(ctx);
+
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.vertical_centered(|ui| {
                 ui.label(RichText::new("Receive").heading().color(WHITE_COLOR));
                 ui.add_space(10.);
 
-                let state = self.shared_state.lock().unwrap();
-                if state.download_progress.is_some() {
-                    let mut text: &str = &self.input_text;
-                    ui.add(egui::TextEdit::multiline(&mut text).font(egui::FontId::monospace(12.)));
-                    ui.add_space(10.);
-                } else {
-                    ui.add(
-                        egui::TextEdit::multiline(&mut self.input_text)
-                            .font(egui::FontId::monospace(12.))
-                            .hint_text("Paste ticket"),
-                    );
-
-                    ui.add_space(10.);
-                    let button = egui::Button::new("Save to...");
-
-                    if ui.add(button).clicked() {
-                        if let Some(path) = rfd::FileDialog::new().pick_folder() {
-                            self.download_target.replace(path);
+                ui.add(
+                    egui::TextEdit::multiline(&mut self.input_text)
+                        .font(egui::FontId::monospace(12.))
+                        .hint_text("Paste ticket or code"),
+                );
+
+                ui.add_space(10.);
+
+                // the dialogs are opened once the lock is released
+                let mut scan_qr = false;
+                let mut pick_target = false;
+                let mut state = self.shared_state.lock().unwrap();
+                match state.preview {
+                    None => {
+                        let has_ticket = !self.input_text.trim().is_empty();
+                        if ui.add_enabled(has_ticket, Button::new("Preview")).clicked() {
+                            // the path of a picture of the ticket
+                            let path = PathBuf::from(self.input_text.trim());
+                            let res = if path.is_file() {
+                                qr::decode(&path).map(|ticket| self.input_text = ticket)
+                            } else {
+                                Ok(())
+                            };
+                            match res {
+                                Ok(()) => {
+                                    self.worker
+                                        .send(WorkerMessage::Preview(self.input_text.clone()))
+                                        .ok();
+                                }
+                                Err(err) => state.errors.push(err.context("scanning QR code")),
+                            }
                         }
+                        ui.add_space(5.);
+                        scan_qr = ui.button("Scan QR code…").clicked();
                     }
-                }
-                if let Some(ref target) = self.download_target {
-                    ui.add_space(5.);
-                    ui.label(format!("Target: {}", target.display()));
-                    ui.add_space(5.);
+                    Some(ref mut preview) => {
+                        let mut close = false;
+                        let mut selected_count = 0;
+                        match preview.files {
+                            None => {
+                                ui.add(egui::Spinner::new());
+                                ui.label("Fetching the file list…");
+                            }
+                            Some(ref files) => {
+                                let (count, size) = files.selected(&preview.selected);
+                                selected_count = count;
+                                ui.label(format!(
+                                    "{} of {} files selected, {}",
+                                    count,
+                                    files.count,
+                                    format_size(size)
+                                ));
+                                ui.add_space(5.);
+                                egui::ScrollArea::vertical()
+                                    .id_source("preview")
+                                    .max_height(150.)
+                                    .show(ui, |ui| {
+                                        ui.with_layout(egui::Layout::top_down(Align::LEFT), |ui| {
+                                            show_file_tree(ui, files, &mut preview.selected)
+                                        });
+                                    });
 
-                    if let Some(_progress) = state.download_progress {
+                                ui.add_space(10.);
+                                let button = egui::Button::new("Save to...");
+
+                                pick_target = ui.add(button).clicked();
+                                if let Some(ref target) = self.download_target {
+                                    ui.add_space(5.);
+                                    ui.label(format!("Target: {}", target.display()));
+                                }
+                            }
+                        }
                         ui.add_space(5.);
-                        ui.add(egui::Spinner::new());
-                    } else {
-                        if ui.button("Download").clicked() {
-                            self.worker
-                                .send(WorkerMessage::Get(self.input_text.clone(), target.clone()))
-                                .ok();
+                        ui.horizontal(|ui| {
+                            if let (Some(_), Some(target)) = (&preview.files, &self.download_target)
+                            {
+                                let download = Button::new("Download");
+                                if ui.add_enabled(selected_count > 0, download).clicked() {
+                                    self.worker
+                                        .send(WorkerMessage::Get(
+                                            preview.ticket.clone(),
+                                            target.clone(),
+                                            preview.selection(),
+                                        ))
+                                        .ok();
+                                    close = true;
+                                }
+                            }
+                            if ui.button("Cancel").clicked() {
+                                preview.cancel.cancel();
+                                close = true;
+                            }
+                        });
+                        if close {
+                            state.preview = None;
                         }
                     }
                 }
+
+                for job in state.jobs.values() {
+                    if job.kind == JobKind::Download {
+                        show_job(ui, job);
+                    }
+                }
+                drop(state);
+
+                if scan_qr {
+                    let path = rfd::FileDialog::new()
+                        .add_filter("Images", &["png", "jpg", "jpeg", "gif", "bmp", "webp"])
+                        .pick_file();
+                    match path.map(|path| qr::decode(&path)) {
+                        Some(Ok(ticket)) => self.input_text = ticket,
+                        Some(Err(err)) => self
+                            .shared_state
+                            .lock()
+                            .unwrap()
+                            .errors
+                            .push(err.context("scanning QR code")),
+                        None => {}
+                    }
+                }
+                if pick_target {
+                    if let Some(path) = rfd::FileDialog::new().pick_folder() {
+                        self.download_target.replace(path);
+                    }
+                }
             });
             ui.add_space(20.);
             ui.separator();
@@ -351,86 +787,486 @@ impl eframe::App for Sapp {
 
                     let button_res = ui.add(button);
                     if button_res.clicked() {
-                        if let Some(path) = rfd::FileDialog::new().pick_file() {
-                            self.selected_file.replace(path);
+                        if let Some(paths) = rfd::FileDialog::new().pick_files() {
+                            self.add_files(ctx, paths);
                         }
                     }
                     preview_files_being_dropped(&button_res.ctx);
 
-                    if let Some(path) = &self.selected_file {
+                    ui.add_space(5.);
+                    if ui.button("Browse folder…").clicked() {
+                        if let Some(path) = rfd::FileDialog::new().pick_folder() {
+                            self.add_files(ctx, [path]);
+                        }
+                    }
+
+                    if !self.selected_files.is_empty() {
                         ui.vertical_centered(|ui| {
                             ui.add_space(25.);
-                            ui.heading("Selected file:");
-                            let name = path
-                                .file_name()
-                                .and_then(|s| s.to_str())
-                                .map(|s| s.to_string())
-                                .unwrap_or_else(|| path.display().to_string());
-                            ui.monospace(&name);
+                            ui.heading("Selected files:");
+                            ui.add_space(5.);
 
-                            ui.add_space(15.);
-                            {
+                            let path_stats = {
                                 let state = self.shared_state.lock().unwrap();
-                                if state.ticket.is_none() {
+                                self.selected_files
+                                    .iter()
+                                    .map(|path| state.path_stats.get(path).copied())
+                                    .collect::<Vec<_>>()
+                            };
+                            let mut remove = None;
+                            for (i, (path, stats)) in
+                                self.selected_files.iter().zip(&path_stats).enumerate()
+                            {
+                                ui.horizontal(|ui| {
+                                    let name = display_name(path);
+                                    if ui.small_button("✖").clicked() {
+                                        remove = Some(i);
+                                    }
+                                    ui.monospace(&name)
+                                        .on_hover_text(path.display().to_string());
+                                    let info = match stats {
+                                        None => "scanning…".to_string(),
+                                        Some(None) => "unreadable".to_string(),
+                                        Some(Some(stats)) if path.is_dir() => format!(
+                                            "{} files, {}",
+                                            stats.files,
+                                            format_size(stats.size)
+                                        ),
+                                        Some(Some(stats)) => format_size(stats.size),
+                                    };
+                                    ui.label(RichText::new(info).color(TEXT_COLOR));
+                                });
+                            }
+                            if let Some(i) = remove {
+                                self.selected_files.remove(i);
+                            }
+
+                            let (files, size) = path_stats
+                                .iter()
+                                .flatten()
+                                .flatten()
+                                .fold((0, 0), |(files, size), stats| {
+                                    (files + stats.files, size + stats.size)
+                                });
+                            ui.add_space(5.);
+                            ui.label(format!("Total: {} files, {}", files, format_size(size)));
+
+                            ui.add_space(5.);
+                            ui.checkbox(&mut self.restrict_shares, "Only to receivers I allow")
+                                .on_hover_text(
+                                    "Refuse everyone else, even if they have the ticket. \
+                                     Add the node ids of the receivers once the share is ready.",
+                                );
+                            self.show_share_policy(ui);
+
+                            ui.add_space(15.);
+                            if !self.selected_files.is_empty() {
+                                ui.horizontal(|ui| {
                                     if ui.button("Share").clicked() {
-                                        self.worker.send(WorkerMessage::Share(path.clone())).ok();
+                                        let paths = std::mem::take(&mut self.selected_files);
+                                        let allowlist = self.new_allowlist();
+                                        self.worker
+                                            .send(WorkerMessage::Share(
+                                                paths,
+                                                allowlist,
+                                                self.share_policy,
+                                            ))
+                                            .ok();
+                                    }
+                                    if ui.button("Clear").clicked() {
+                                        self.selected_files.clear();
+                                    }
+                                });
+                            }
+                        });
+                    }
+
+                    let mut state = self.shared_state.lock().unwrap();
+                    for job in state.jobs.values() {
+                        if job.kind == JobKind::Share {
+                            show_job(ui, job);
+                        }
+                    }
+
+                    if !state.shares.is_empty() {
+                        ui.add_space(25.);
+                        ui.heading("Active shares:");
+                        let mut errors = Vec::new();
+                        for share in &state.shares {
+                            ui.add_space(10.);
+                            egui::Frame::group(ui.style()).show(ui, |ui| {
+                                ui.vertical_centered(|ui| {
+                                    ui.label(RichText::new(&share.name).color(WHITE_COLOR));
+                                    let size = share.share.size;
+                                    ui.label(format!(
+                                        "{}, up for {}",
+                                        format_size(size),
+                                        format_duration(share.started.elapsed())
+                                    ));
+                                    let mut limits = Vec::new();
+                                    if let Some(left) = share.share.time_left() {
+                                        limits
+                                            .push(format!("expires in {}", format_duration(left)));
+                                    }
+                                    match share.share.downloads_left() {
+                                        Some(1) => limits.push("1 download left".to_string()),
+                                        Some(left) => {
+                                            limits.push(format!("{} downloads left", left))
+                                        }
+                                        None => {}
+                                    }
+                                    if !limits.is_empty() {
+                                        ui.label(limits.join(", "));
+                                    }
+                                    if let Some((to, status)) = &share.offer {
+                                        ui.label(format!("{} {}", status.label(), to));
                                     }
-                                }
-                                if let Some(_progress) = state.sharing_progress {
                                     ui.add_space(5.);
-                                    ui.add(egui::Spinner::new());
-                                }
 
-                                if let Some(ref ticket) = state.ticket {
+                                    if let Some(code) = &share.code {
+                                        ui.horizontal(|ui| {
+                                            let code = code.to_string();
+                                            ui.label(
+                                                RichText::new(&code)
+                                                    .monospace()
+                                                    .size(18.)
+                                                    .color(WHITE_COLOR),
+                                            );
+                                            if ui.small_button("Copy").clicked() {
+                                                ui.output_mut(|o| o.copied_text = code);
+                                            }
+                                        });
+                                        ui.add_space(5.);
+                                    }
+
+                                    for (connection_id, peer) in &share.peers {
+                                        show_peer_progress(ui, *connection_id, peer, size);
+                                    }
+
                                     // selectable text
-                                    let ticket_text = ticket.to_string();
+                                    let ticket_text = share.share.ticket.to_string();
                                     let mut text: &str = &ticket_text;
-                                    ui.vertical_centered(|ui| {
-                                        ui.heading("Ready to share:");
-                                        ui.add_space(10.);
-                                        ui.add(
-                                            egui::TextEdit::multiline(&mut text)
-                                                .font(egui::FontId::monospace(12.)),
-                                        );
-                                    });
-                                }
-                            }
-                        });
+                                    ui.add(
+                                        egui::TextEdit::multiline(&mut text)
+                                            .font(egui::FontId::monospace(12.)),
+                                    );
+
+                                    if !self.qr_codes.contains_key(&share.id) {
+                                        match qr::encode(&ticket_text) {
+                                            Ok(image) => {
+                                                let texture = ui.ctx().load_texture(
+                                                    format!("qr-{}", share.id),
+                                                    image,
+                                                    egui::TextureOptions::NEAREST,
+                                                );
+                                                self.qr_codes.insert(share.id, texture);
+                                            }
+                                            Err(err) => warn!("failed to make QR code: {:?}", err),
+                                        }
+                                    }
+                                    if let Some(texture) = self.qr_codes.get(&share.id) {
+                                        egui::CollapsingHeader::new("QR code")
+                                            .id_source(("qr", share.id))
+                                            .show(ui, |ui| {
+                                                ui.image((texture.id(), vec2(200., 200.)));
+                                            });
+                                    }
+                                    let input = self.allow_inputs.entry(share.id).or_default();
+                                    if let Err(err) =
+                                        show_allowlist(ui, share, input, &state.nearby)
+                                    {
+                                        errors.push(err);
+                                    }
+
+                                    ui.add_space(5.);
+                                    if ui.button("Stop").clicked() {
+                                        self.worker.send(WorkerMessage::StopShare(share.id)).ok();
+                                    }
+                                });
+                            });
+                        }
+                        state.errors.extend(errors);
+                        // keep the uptime ticking
+                        ctx.request_repaint_after(Duration::from_secs(1));
                     }
                 });
             });
         });
 
         // Collect dropped files:
-        ctx.input(|i| {
-            if !i.raw.dropped_files.is_empty() {
-                if let Some(ref path) = i.raw.dropped_files[0].path {
-                    self.selected_file.replace(path.clone());
-                    self.shared_state.lock().unwrap().reset_download();
-                }
-            }
+        let dropped = ctx.input(|i| {
+            i.raw
+                .dropped_files
+                .iter()
+                .filter_map(|file| file.path.clone())
+                .collect::<Vec<_>>()
         });
+        // a single picture of a ticket fills in the ticket, anything else is shared
+        let scanned = match dropped.as_slice() {
+            [path] if qr::is_image(path) => qr::decode(path)
+                .ok()
+                .filter(|text| sendme::parse_ticket(text).is_ok()),
+            _ => None,
+        };
+        match scanned {
+            Some(ticket) => self.input_text = ticket,
+            None => self.add_files(ctx, dropped),
+        }
+        {
+            let state = self.shared_state.lock().unwrap();
+            self.qr_codes
+                .retain(|id, _| state.shares.iter().any(|share| share.id == *id));
+            self.allow_inputs
+                .retain(|id, _| state.shares.iter().any(|share| share.id == *id));
+        }
+
+        self.show_offers(ctx);
 
         // Show potential errors
         self.show_errors(&ctx);
     }
 }
 
+/// Show who may fetch `share`, and let the user allow or revoke node ids.
+///
+/// `input` holds the node id being entered. Fails if it is not a node id.
+fn show_allowlist(
+    ui: &mut egui::Ui,
+    share: &ActiveShare,
+    input: &mut String,
+    nearby: &[sendme::NearbyDevice],
+) -> anyhow::Result<()> {
+    let allowlist = share.share.allowlist();
+    let node_ids = allowlist.node_ids();
+    let title = match (allowlist.is_restricted(), node_ids.len()) {
+        (false, _) => "Receivers: anyone with the ticket".to_string(),
+        (true, 0) => "Receivers: nobody yet".to_string(),
+        (true, n) => format!("Receivers: {} allowed", n),
+    };
+    let mut res = Ok(());
+    egui::CollapsingHeader::new(title)
+        .id_source(("allowlist", share.id))
+        .show(ui, |ui| {
+            for node_id in &node_ids {
+                ui.horizontal(|ui| {
+                    ui.monospace(node_id.fmt_short())
+                        .on_hover_text(node_id.to_string());
+                    if ui.small_button("Revoke").clicked() {
+                        allowlist.revoke(node_id);
+                    }
+                });
+            }
+            for device in nearby {
+                let node_id = device.addr.node_id;
+                if !node_ids.contains(&node_id)
+                    && ui
+                        .small_button(format!("Allow {}", device.name))
+                        .on_hover_text(node_id.to_string())
+                        .clicked()
+                {
+                    allowlist.allow(node_id);
+                }
+            }
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(input).hint_text("Node id of the receiver"));
+                if ui
+                    .add_enabled(!input.trim().is_empty(), Button::new("Allow"))
+                    .clicked()
+                {
+                    match input.trim().parse::<PublicKey>() {
+                        Ok(node_id) => {
+                            allowlist.allow(node_id);
+                            input.clear();
+                        }
+                        Err(err) => res = Err(anyhow::Error::new(err).context("invalid node id")),
+                    }
+                }
+            });
+            ui.label(
+                RichText::new("Receivers find their node id in the settings.").color(TEXT_COLOR),
+            );
+        });
+    res
+}
+
+/// Show a running job with its progress and a button to cancel it.
+fn show_job(ui: &mut egui::Ui, job: &Job) {
+    ui.add_space(10.);
+    egui::Frame::group(ui.style()).show(ui, |ui| {
+        ui.vertical_centered(|ui| {
+            ui.label(RichText::new(&job.name).color(WHITE_COLOR));
+            ui.add_space(5.);
+            show_transfer_progress(ui, &job.progress);
+            if ui.button("Cancel").clicked() {
+                job.cancel.cancel();
+            }
+        });
+    });
+}
+
+/// Show the files of a collection as a collapsible directory tree.
+///
+/// Ticking a file or directory selects it for download, a directory
+/// toggles all files below it.
+fn show_file_tree(ui: &mut egui::Ui, tree: &FileTree, selected: &mut [bool]) {
+    for (name, dir) in &tree.dirs {
+        let indices = dir.indices();
+        let mut all = indices.iter().all(|&i| selected[i]);
+        let id = ui.make_persistent_id(name);
+        egui::collapsing_header::CollapsingState::load_with_default_open(ui.ctx(), id, false)
+            .show_header(ui, |ui| {
+                if ui.checkbox(&mut all, format!("{}/", name)).changed() {
+                    for &i in &indices {
+                        selected[i] = all;
+                    }
+                }
+                let info = format!("{} files, {}", dir.count, format_size(dir.size));
+                ui.label(RichText::new(info).color(TEXT_COLOR));
+            })
+            .body(|ui| show_file_tree(ui, dir, selected));
+    }
+    for file in &tree.files {
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut selected[file.index], &file.name);
+            ui.label(RichText::new(format_size(file.size)).color(TEXT_COLOR));
+        });
+    }
+}
+
+fn show_transfer_progress(ui: &mut egui::Ui, progress: &TransferProgress) {
+    if progress.total() == 0 {
+        // nothing to measure yet
+        ui.add(egui::Spinner::new());
+        return;
+    }
+    if progress.resumed() > 0 {
+        ui.label(format!(
+            "resuming, {} of {} already present",
+            format_size(progress.resumed()),
+            format_size(progress.total())
+        ));
+    }
+    ui.add(
+        egui::ProgressBar::new(progress.fraction())
+            .desired_width(250.)
+            .text(format!(
+                "{} of {}",
+                format_size(progress.done()),
+                format_size(progress.total())
+            )),
+    );
+    if let Some(current) = progress.current() {
+        let name = Path::new(current)
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or(current);
+        ui.label(RichText::new(name).monospace().color(TEXT_COLOR));
+    }
+    let eta = progress
+        .eta()
+        .map(|eta| format!(", {} left", format_duration(eta)))
+        .unwrap_or_default();
+    ui.label(format!(
+        "{}/s{}",
+        format_size(progress.throughput() as u64),
+        eta
+    ));
+    // keep throughput and eta fresh while no events arrive
+    ui.ctx().request_repaint_after(Duration::from_millis(500));
+}
+
+fn show_peer_progress(ui: &mut egui::Ui, connection_id: u64, peer: &PeerProgress, size: u64) {
+    let blobs = match peer.blobs_total {
+        Some(total) => format!("{}/{} blobs", peer.blobs_sent, total),
+        None => format!("{} blobs", peer.blobs_sent),
+    };
+    ui.label(format!(
+        "Peer #{}: {}, {} requests, {}",
+        connection_id,
+        peer.status.label(),
+        peer.requests,
+        blobs
+    ));
+    let progress = if peer.status == PeerStatus::Done {
+        1.
+    } else if size > 0 {
+        (peer.bytes_sent as f32 / size as f32).min(1.)
+    } else {
+        0.
+    };
+    ui.add(
+        egui::ProgressBar::new(progress)
+            .desired_width(250.)
+            .text(format!(
+                "{} of {}",
+                format_size(peer.bytes_sent.min(size)),
+                format_size(size)
+            )),
+    );
+    ui.add_space(5.);
+}
+
+/// The file name of a path, or the full path if it has none.
+fn display_name(path: &Path) -> String {
+    path.file_name()
+        .and_then(|s| s.to_str())
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+/// Name a share after its paths, e.g. `photos and 2 more`.
+fn share_name(paths: &[PathBuf]) -> String {
+    match paths {
+        [] => String::new(),
+        [path] => display_name(path),
+        [path, rest @ ..] => format!("{} and {} more", display_name(path), rest.len()),
+    }
+}
+
+/// Format a duration for display, e.g. `1h 02m` or `3m 12s`.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}h {:02}m", secs / 3600, secs % 3600 / 60)
+    } else if secs >= 60 {
+        format!("{}m {:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+/// Format a byte count for display, e.g. `3.4 MiB`.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024. && unit < UNITS.len() - 1 {
+        size /= 1024.;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
 /// Preview hovering files:
 fn preview_files_being_dropped(ctx: &egui::Context) {
     use egui::*;
 
     if !ctx.input(|i| i.raw.hovered_files.is_empty()) {
         let text = ctx.input(|i| {
-            if i.raw.hovered_files.len() == 1 {
-                let file = &i.raw.hovered_files[0];
-                if let Some(ref path) = file.path {
-                    format!("Dropping file:\n{}", path.display())
-                } else {
-                    "Invalid file".into()
-                }
-            } else {
-                "Only one file is allowed".into()
+            let paths = i
+                .raw
+                .hovered_files
+                .iter()
+                .filter_map(|file| file.path.as_ref())
+                .collect::<Vec<_>>();
+            match paths.as_slice() {
+                [] => "Invalid file".into(),
+                [path] => format!("Dropping file:\n{}", path.display()),
+                paths => format!("Dropping {} files", paths.len()),
             }
         });
 
@@ -469,3 +1305,17 @@ fn macos_resource_path() -> Option<PathBuf> {
 fn macos_resource_path() -> Option<PathBuf> {
     None
 }
+
+/// Attach to the console of the parent process, if it has one.
+#[cfg(windows)]
+fn attach_console() {
+    use windows_sys::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+    // SAFETY: AttachConsole has no preconditions. It fails if this process
+    // already has a console or the parent has none, which is fine.
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(windows))]
+fn attach_console() {}
@@ -0,0 +1,271 @@
+//! Progress of imports and downloads, for progress bars.
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use iroh_bytes::{get::db::DownloadProgress, store::ImportProgress};
+
+use crate::get::GetProgress;
+
+/// Byte accurate progress of an import or a download.
+#[derive(Debug, Clone)]
+pub struct TransferProgress {
+    started: Instant,
+    /// Total number of bytes, as far as known.
+    total: u64,
+    /// Bytes of items that are done.
+    finished: u64,
+    /// Bytes that were already present when the transfer started.
+    resumed: u64,
+    /// Items that are in progress, by id.
+    items: HashMap<u64, Item>,
+    /// Id of the item that made progress most recently.
+    current: Option<u64>,
+    /// Names of the files of a download, in the order they are fetched.
+    names: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Item {
+    name: Option<String>,
+    size: u64,
+    offset: u64,
+}
+
+impl TransferProgress {
+    /// Create a tracker for a transfer of `total` bytes.
+    pub fn new(total: u64) -> Self {
+        Self {
+            started: Instant::now(),
+            total,
+            finished: 0,
+            resumed: 0,
+            items: HashMap::new(),
+            current: None,
+            names: Vec::new(),
+        }
+    }
+
+    /// Total number of bytes, as far as known.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Update the total, e.g. once the sizes of a download are known.
+    pub fn set_total(&mut self, total: u64) {
+        self.total = total;
+    }
+
+    /// Number of bytes done so far.
+    pub fn done(&self) -> u64 {
+        let in_progress = self.items.values().map(|item| item.offset).sum::<u64>();
+        (self.finished + in_progress).min(self.total)
+    }
+
+    /// Fraction of the transfer that is done, between 0 and 1.
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            return 0.;
+        }
+        self.done() as f32 / self.total as f32
+    }
+
+    /// Name of the file that is currently being processed.
+    pub fn current(&self) -> Option<&str> {
+        self.current
+            .and_then(|id| self.items.get(&id))
+            .and_then(|item| item.name.as_deref())
+    }
+
+    /// Bytes that were already present when the transfer started.
+    pub fn resumed(&self) -> u64 {
+        self.resumed
+    }
+
+    /// Count `bytes` that are already present as done.
+    ///
+    /// They do not count towards the throughput.
+    pub fn resume(&mut self, bytes: u64) {
+        self.resumed += bytes;
+        self.finished += bytes;
+    }
+
+    /// Average throughput in bytes per second since the transfer started.
+    pub fn throughput(&self) -> f64 {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        if elapsed == 0. {
+            return 0.;
+        }
+        self.done().saturating_sub(self.resumed) as f64 / elapsed
+    }
+
+    /// Estimated time until the transfer is done, if it is making progress.
+    pub fn eta(&self) -> Option<Duration> {
+        let throughput = self.throughput();
+        if throughput < 1. {
+            return None;
+        }
+        let remaining = self.total.saturating_sub(self.done());
+        Some(Duration::from_secs_f64(remaining as f64 / throughput))
+    }
+
+    /// Update the progress from an import event.
+    pub fn on_import(&mut self, event: ImportProgress) {
+        match event {
+            ImportProgress::Found { id, name } => {
+                self.items.entry(id).or_default().name = Some(name);
+                self.current = Some(id);
+            }
+            ImportProgress::Size { id, size } => {
+                self.items.entry(id).or_default().size = size;
+            }
+            ImportProgress::OutboardProgress { id, offset } => {
+                self.items.entry(id).or_default().offset = offset;
+                self.current = Some(id);
+            }
+            ImportProgress::OutboardDone { id, .. } => self.finish(id),
+            _ => {}
+        }
+    }
+
+    /// Update the progress from a download event.
+    pub fn on_download(&mut self, event: DownloadProgress) {
+        match event {
+            DownloadProgress::Found {
+                id, child, size, ..
+            } => {
+                // child 0 is the hash sequence, child 1 the collection metadata
+                let name = child.checked_sub(2).map(|i| {
+                    self.names
+                        .get(i as usize)
+                        .cloned()
+                        .unwrap_or_else(|| format!("file {}", i + 1))
+                });
+                self.items.insert(
+                    id,
+                    Item {
+                        name,
+                        size,
+                        offset: 0,
+                    },
+                );
+                self.current = Some(id);
+            }
+            DownloadProgress::Progress { id, offset } => {
+                if let Some(item) = self.items.get_mut(&id) {
+                    item.offset = offset;
+                }
+                self.current = Some(id);
+            }
+            DownloadProgress::Done { id } => self.finish(id),
+            DownloadProgress::AllDone => {
+                self.items.clear();
+                self.finished = self.total;
+            }
+            _ => {}
+        }
+    }
+
+    /// Update the progress from an event of [`crate::get()`].
+    pub fn on_get(&mut self, event: GetProgress) {
+        match event {
+            GetProgress::Sizes(sizes) => {
+                // the hash sequence itself is 32 bytes per blob
+                let hash_seq = 32 * sizes.len() as u64;
+                let total = sizes.iter().sum::<u64>();
+                self.set_total(hash_seq + total);
+            }
+            GetProgress::Names(names) => self.names = names,
+            GetProgress::Resuming { present, .. } => self.resume(present),
+            GetProgress::Download(ev) => self.on_download(ev),
+            GetProgress::FileDone(_) => {}
+        }
+    }
+
+    fn finish(&mut self, id: u64) {
+        if let Some(item) = self.items.remove(&id) {
+            self.finished += item.size.max(item.offset);
+        }
+        if self.current == Some(id) {
+            self.current = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh_bytes::Hash;
+
+    use super::*;
+
+    fn found(id: u64, child: u64, size: u64) -> DownloadProgress {
+        DownloadProgress::Found {
+            id,
+            child,
+            hash: Hash::new(b"data"),
+            size,
+        }
+    }
+
+    #[test]
+    fn download_progress() {
+        let mut progress = TransferProgress::new(0);
+        progress.on_get(GetProgress::Sizes(vec![100, 1000, 2000]));
+        assert_eq!(progress.total(), 32 * 3 + 3100);
+        progress.on_get(GetProgress::Names(vec!["a.txt".into(), "dir/b.txt".into()]));
+
+        // the metadata has no name
+        progress.on_download(found(1, 1, 100));
+        assert_eq!(progress.current(), None);
+        progress.on_download(DownloadProgress::Done { id: 1 });
+
+        progress.on_download(found(2, 3, 2000));
+        progress.on_download(DownloadProgress::Progress { id: 2, offset: 500 });
+        assert_eq!(progress.current(), Some("dir/b.txt"));
+        assert_eq!(progress.done(), 600);
+        progress.on_download(DownloadProgress::Done { id: 2 });
+        assert_eq!(progress.done(), 2100);
+        assert_eq!(progress.current(), None);
+
+        progress.on_download(DownloadProgress::AllDone);
+        assert_eq!(progress.done(), progress.total());
+        assert_eq!(progress.fraction(), 1.);
+    }
+
+    #[test]
+    fn files_without_names_are_numbered() {
+        let mut progress = TransferProgress::new(100);
+        progress.on_download(found(1, 2, 100));
+        assert_eq!(progress.current(), Some("file 1"));
+    }
+
+    #[test]
+    fn resumed_bytes_are_done_but_not_throughput() {
+        let mut progress = TransferProgress::new(1000);
+        progress.resume(400);
+        assert_eq!(progress.done(), 400);
+        assert_eq!(progress.resumed(), 400);
+        assert_eq!(progress.throughput(), 0.);
+        assert_eq!(progress.eta(), None);
+        // done never exceeds the total
+        progress.resume(800);
+        assert_eq!(progress.done(), 1000);
+    }
+
+    #[test]
+    fn eta_from_throughput() {
+        let mut progress = TransferProgress::new(3000);
+        progress.resume(1000);
+        progress.started = Instant::now() - Duration::from_secs(10);
+        progress.on_download(found(1, 2, 2000));
+        progress.on_download(DownloadProgress::Progress {
+            id: 1,
+            offset: 1000,
+        });
+        // 1000 bytes in 10 seconds, and 1000 bytes left
+        assert!((progress.throughput() - 100.).abs() < 1.);
+        let eta = progress.eta().unwrap();
+        assert!((eta.as_secs_f64() - 10.).abs() < 0.5);
+    }
+}
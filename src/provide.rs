@@ -0,0 +1,466 @@
+//! Serving collections to other devices.
+use std::{
+    fs::{File, OpenOptions},
+    future::Future,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use futures::{
+    future::{self, BoxFuture},
+    FutureExt,
+};
+use iroh_bytes::{
+    format::collection::Collection,
+    provider::{handle_connection, Event, EventSender},
+    store::ImportProgress,
+    BlobFormat,
+};
+use iroh_net::{ticket::BlobTicket, MagicEndpoint, NodeAddr};
+use rand::Rng;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio_util::{sync::CancellationToken, task::LocalPoolHandle};
+use tracing::{debug, info, warn};
+
+use crate::{
+    allowlist::{Allowlist, Downloads, Gate},
+    collection::import,
+    data_dir,
+    endpoint::{self, RelayMode},
+    error::{Result, SendmeError},
+    inbox,
+};
+
+/// Prefix of the store directories created by [`provide`].
+const PROVIDE_DIR_PREFIX: &str = "provide-";
+
+/// Lock file inside a provide store, held for as long as the store is in use.
+const LOCK_FILE: &str = "lock";
+
+/// Create a fresh store directory for a share in the app data dir.
+///
+/// The returned file holds the lock on the store. It must be kept alive for as
+/// long as the store is in use, so [`cleanup_provide_stores`] leaves it alone.
+fn create_provide_dir() -> anyhow::Result<(PathBuf, File)> {
+    let suffix = rand::thread_rng().gen::<[u8; 16]>();
+    let dir = data_dir()?.join(format!("{}{}", PROVIDE_DIR_PREFIX, hex::encode(suffix)));
+    anyhow::ensure!(!dir.exists(), "store {} already exists", dir.display());
+    std::fs::create_dir_all(&dir)
+        .with_context(|| format!("failed to create store {}", dir.display()))?;
+    let lock = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(dir.join(LOCK_FILE))?;
+    lock.try_lock()
+        .with_context(|| format!("failed to lock store {}", dir.display()))?;
+    Ok((dir, lock))
+}
+
+/// Remove provide stores left behind by sessions that did not shut down cleanly.
+///
+/// Stores whose lock is held by a running process are kept.
+pub fn cleanup_provide_stores() -> Result<()> {
+    let dir = data_dir()?;
+    if !dir.exists() {
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(&dir).map_err(SendmeError::store)? {
+        let path = entry.map_err(SendmeError::store)?.path();
+        let is_store = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(PROVIDE_DIR_PREFIX));
+        if !is_store || !path.is_dir() {
+            continue;
+        }
+        let lock = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path.join(LOCK_FILE));
+        if let Ok(lock) = lock {
+            if lock.try_lock().is_err() {
+                // still in use by another instance
+                continue;
+            }
+        }
+        info!("removing orphaned store {}", path.display());
+        if let Err(err) = std::fs::remove_dir_all(&path) {
+            warn!("failed to remove {}: {:?}", path.display(), err);
+        }
+    }
+    Ok(())
+}
+
+/// When a share stops serving by itself.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SharePolicy {
+    /// Stop this long after the share is ready.
+    pub expires_after: Option<Duration>,
+    /// Stop once this many receivers got the whole collection. With `Some(1)`,
+    /// the share stops after the first successful receiver. Transfers that are
+    /// still running then finish, new connections are refused.
+    pub max_downloads: Option<u64>,
+}
+
+/// A running share, created by [`provide`].
+///
+/// The share keeps serving its collection until [`Share::stop`] is called, or
+/// until its [`SharePolicy`] ends it.
+#[derive(Debug)]
+pub struct Share {
+    /// Ticket to fetch the collection.
+    pub ticket: BlobTicket,
+    /// Total size of the shared files.
+    pub size: u64,
+    /// Names and hashes of the shared files.
+    pub collection: Collection,
+    /// When the share stops serving by itself.
+    pub policy: SharePolicy,
+    allowlist: Allowlist,
+    /// When the share was ready.
+    started: Instant,
+    /// Number of receivers that got the whole collection.
+    downloads: Arc<AtomicU64>,
+    /// Cancelled once the share stopped serving.
+    ended: CancellationToken,
+    endpoint: MagicEndpoint,
+    handle: JoinHandle<()>,
+}
+
+impl Share {
+    /// Stop serving, release the shared data and delete the store.
+    pub async fn stop(self) -> Result<()> {
+        // closing the endpoint ends the accept loop, which cleans up the store.
+        // A share ended by its policy closed it already.
+        if !self.ended.is_cancelled() {
+            self.endpoint
+                .close(0u32.into(), b"share stopped")
+                .await
+                .map_err(SendmeError::other)?;
+        }
+        self.handle.await.map_err(SendmeError::other)?;
+        Ok(())
+    }
+
+    /// Time until the share expires, `None` if it does not.
+    pub fn time_left(&self) -> Option<Duration> {
+        let expires_after = self.policy.expires_after?;
+        Some(expires_after.saturating_sub(self.started.elapsed()))
+    }
+
+    /// Number of receivers that got the whole collection so far.
+    ///
+    /// Only counted for shares with a download limit or an allowlist, which
+    /// see the requests of each receiver.
+    pub fn downloads(&self) -> u64 {
+        self.downloads.load(Ordering::Relaxed)
+    }
+
+    /// Downloads until the share stops, `None` if there is no limit.
+    pub fn downloads_left(&self) -> Option<u64> {
+        let max_downloads = self.policy.max_downloads?;
+        Some(max_downloads.saturating_sub(self.downloads()))
+    }
+
+    /// Resolves once the share stopped serving, because of its policy or
+    /// because it was stopped.
+    ///
+    /// The share still has to be stopped with [`Share::stop`] to wait for the
+    /// store to be removed.
+    pub fn ended(&self) -> impl Future<Output = ()> + Send + 'static {
+        let ended = self.ended.clone();
+        async move { ended.cancelled().await }
+    }
+
+    /// The node ids that may fetch this share.
+    pub fn allowlist(&self) -> &Allowlist {
+        &self.allowlist
+    }
+
+    /// Offer this share to the [`Inbox`](crate::Inbox) at `to`, announcing
+    /// this device as `name`.
+    ///
+    /// The returned future waits until the user of the inbox decides and
+    /// resolves to whether they accepted. It does not borrow the share, which
+    /// keeps running either way.
+    pub fn offer(
+        &self,
+        to: NodeAddr,
+        name: String,
+    ) -> impl Future<Output = Result<bool>> + Send + 'static {
+        inbox::send_offer(self, self.endpoint.clone(), to, name)
+    }
+}
+
+/// Import `paths` into a new collection and serve it until the share is stopped.
+///
+/// Provider events, such as peers connecting and transfers completing, are
+/// sent to `events`. Peers that are not on `allowlist` are refused before
+/// anything is served. The share stops serving by itself as set by `policy`.
+///
+/// With [`RelayMode::Disabled`], or if no relay server can be reached, the
+/// ticket only contains the direct addresses of this device.
+///
+/// Cancelling `cancel` before the share is ready aborts the import and removes
+/// the store.
+pub async fn provide(
+    paths: Vec<PathBuf>,
+    relay: RelayMode,
+    allowlist: Allowlist,
+    policy: SharePolicy,
+    import_progress: flume::Sender<ImportProgress>,
+    events: flume::Sender<Event>,
+    cancel: CancellationToken,
+) -> Result<Share> {
+    let endpoint = endpoint::bind(vec![iroh_bytes::protocol::ALPN.to_vec()], &relay).await?;
+
+    // use a flat store in the app data dir. The files are imported by
+    // reference, so the store only holds the outboards and the collection.
+    let (iroh_data_dir, lock) = create_provide_dir().map_err(SendmeError::store)?;
+    let res = async {
+        let db = iroh_bytes::store::flat::Store::load(&iroh_data_dir)
+            .await
+            .map_err(SendmeError::store)?;
+        let (temp_tag, size, collection) =
+            import(paths.clone(), db.clone(), import_progress).await?;
+
+        // make a ticket
+        let addr = endpoint::node_addr(&endpoint, &relay).await?;
+        let ticket = BlobTicket::new(addr, *temp_tag.hash(), BlobFormat::HashSeq)?;
+        Ok((db, temp_tag, size, collection, ticket))
+    };
+    let res = tokio::select! {
+        res = res => res,
+        _ = cancel.cancelled() => Err(SendmeError::Cancelled),
+    };
+    let (db, temp_tag, size, collection, ticket) = match res {
+        Ok(res) => res,
+        Err(err) => {
+            endpoint.close(0u32.into(), b"share failed").await.ok();
+            drop(lock);
+            std::fs::remove_dir_all(&iroh_data_dir).ok();
+            return Err(err);
+        }
+    };
+    info!(
+        "sharing {} files, {} bytes, hash {}",
+        collection.len(),
+        size,
+        ticket.hash()
+    );
+    let files = collection.iter().map(|(_, hash)| *hash).collect();
+    let started = Instant::now();
+    let downloads = Arc::new(AtomicU64::new(0));
+    let ended = CancellationToken::new();
+    let handle = tokio::task::spawn({
+        let endpoint = endpoint.clone();
+        let allowlist = allowlist.clone();
+        let downloads = downloads.clone();
+        let ended = ended.clone();
+        async move {
+            let rt = LocalPoolHandle::new(1);
+            let (done_send, done_recv) = flume::unbounded();
+            let tally = Downloads::new(*temp_tag.hash(), files, done_send);
+            let expired = async {
+                match policy.expires_after {
+                    Some(expires_after) => tokio::time::sleep(expires_after).await,
+                    None => future::pending().await,
+                }
+            };
+            tokio::pin!(expired);
+            // started once the share is restricted or needs to count downloads.
+            // Counting needs the gate even if anyone may download: only the
+            // requests tell a preview from a download, the provider events
+            // report both the same way.
+            let mut gate = None;
+            let mut connections = JoinSet::new();
+            // at its download limit, the share refuses new connections but
+            // lets the transfers in flight finish
+            let mut draining = false;
+            let mut ended_by_policy = false;
+            loop {
+                let connecting = tokio::select! {
+                    connecting = endpoint.accept() => match connecting {
+                        Some(connecting) => connecting,
+                        None => break,
+                    },
+                    _ = &mut expired => {
+                        info!("share expired");
+                        ended_by_policy = true;
+                        break;
+                    }
+                    Ok(_) = done_recv.recv_async() => {
+                        let count = downloads.fetch_add(1, Ordering::Relaxed) + 1;
+                        if !draining && policy.max_downloads.is_some_and(|max| count >= max) {
+                            info!(
+                                "share reached {} downloads, finishing {} connections",
+                                count,
+                                connections.len()
+                            );
+                            ended_by_policy = true;
+                            draining = true;
+                            if connections.is_empty() {
+                                break;
+                            }
+                        }
+                        continue;
+                    }
+                    Some(_) = connections.join_next() => {
+                        if draining && connections.is_empty() {
+                            break;
+                        }
+                        continue;
+                    }
+                };
+                if draining {
+                    // dropping the connection refuses it
+                    debug!("refused a connection, the share reached its download limit");
+                    continue;
+                }
+                debug!("incoming connection");
+                let db = db.clone();
+                let rt = rt.clone();
+                let events = Events(events.clone());
+                if !allowlist.is_restricted() && policy.max_downloads.is_none() {
+                    connections.spawn(handle_connection(connecting, db, events, rt));
+                    continue;
+                }
+                if gate.is_none() {
+                    match Gate::start(db, events, rt, tally.clone()) {
+                        Ok(started) => gate = Some(Arc::new(started)),
+                        Err(err) => {
+                            // dropping the connection refuses it
+                            warn!("failed to start gate: {:?}", err);
+                            continue;
+                        }
+                    }
+                }
+                let gate = gate.clone().expect("gate started");
+                let allowlist = allowlist.clone();
+                connections.spawn(async move {
+                    if let Err(err) = gate.handle(connecting, allowlist).await {
+                        warn!("connection failed: {:?}", err);
+                    }
+                });
+            }
+            if let Some(gate) = gate {
+                gate.close();
+            }
+            if ended_by_policy {
+                endpoint.close(0u32.into(), b"share ended").await.ok();
+            }
+            ended.cancel();
+            drop(temp_tag);
+            drop(lock);
+            if let Err(err) = std::fs::remove_dir_all(&iroh_data_dir) {
+                warn!("failed to remove {}: {:?}", iroh_data_dir.display(), err);
+            }
+            debug!("share stopped");
+        }
+    });
+    Ok(Share {
+        ticket,
+        size,
+        collection,
+        policy,
+        allowlist,
+        started,
+        downloads,
+        ended,
+        endpoint,
+        handle,
+    })
+}
+
+/// Forwards provider events to a channel.
+#[derive(Debug, Clone)]
+struct Events(flume::Sender<Event>);
+
+impl EventSender for Events {
+    fn send(&self, event: Event) -> BoxFuture<()> {
+        // the channel is unbounded, so a slow consumer never stalls a transfer
+        self.0.send(event).ok();
+        future::ready(()).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Once;
+
+    use iroh_net::key::SecretKey;
+
+    use super::*;
+    use crate::{get, identity, preview};
+
+    /// Use a key of the tests, not the one of the user.
+    fn test_identity() {
+        static SET: Once = Once::new();
+        SET.call_once(|| {
+            std::env::set_var(identity::SECRET_ENV, SecretKey::generate().to_string())
+        });
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn share_ends_once_its_files_were_downloaded() {
+        test_identity();
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("hello.txt");
+        std::fs::write(&file, b"hello world").unwrap();
+        let policy = SharePolicy {
+            max_downloads: Some(1),
+            ..Default::default()
+        };
+        let (import_progress, _import_progress) = flume::unbounded();
+        let (events, _events) = flume::unbounded();
+        let share = provide(
+            vec![file],
+            RelayMode::Disabled,
+            Allowlist::anyone(),
+            policy,
+            import_progress,
+            events,
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+
+        // the file is smaller than a chunk, so the preview gets all of it
+        let files = preview(&share.ticket, &RelayMode::Disabled)
+            .await
+            .unwrap()
+            .files;
+        assert_eq!(files, [("hello.txt".to_string(), 11)]);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(share.downloads(), 0);
+
+        let target = dir.path().join("downloads");
+        std::fs::create_dir(&target).unwrap();
+        let (progress, _progress) = flume::unbounded();
+        get(
+            share.ticket.clone(),
+            target.clone(),
+            None,
+            RelayMode::Disabled,
+            progress,
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            std::fs::read(target.join("hello.txt")).unwrap(),
+            b"hello world"
+        );
+        tokio::time::timeout(Duration::from_secs(10), share.ended())
+            .await
+            .expect("share did not end");
+        assert_eq!(share.downloads(), 1);
+        share.stop().await.unwrap();
+    }
+}
@@ -0,0 +1,82 @@
+//! Tickets as QR codes, to move them to phones or across an air gap.
+use std::path::Path;
+
+use anyhow::Context;
+use eframe::egui::ColorImage;
+use qrcode::{Color, QrCode};
+
+/// Width of the light border around the code, in modules.
+const QUIET_ZONE: usize = 4;
+
+/// Render `text` as a QR code, with one pixel per module.
+pub fn encode(text: &str) -> anyhow::Result<ColorImage> {
+    let code = QrCode::new(text.as_bytes())?;
+    let width = code.width();
+    let size = width + 2 * QUIET_ZONE;
+    let mut pixels = vec![u8::MAX; size * size];
+    for (i, color) in code.to_colors().into_iter().enumerate() {
+        if color == Color::Dark {
+            let (x, y) = (i % width + QUIET_ZONE, i / width + QUIET_ZONE);
+            pixels[y * size + x] = 0;
+        }
+    }
+    Ok(ColorImage::from_gray([size, size], &pixels))
+}
+
+/// Read the text of the first QR code found in the image at `path`.
+pub fn decode(path: &Path) -> anyhow::Result<String> {
+    let image = image::open(path)
+        .with_context(|| format!("failed to open {}", path.display()))?
+        .to_luma8();
+    let mut image = rqrr::PreparedImage::prepare(image);
+    let mut last_err = None;
+    for grid in image.detect_grids() {
+        match grid.decode() {
+            Ok((_, text)) => return Ok(text),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    match last_err {
+        Some(err) => Err(anyhow::anyhow!("failed to read the QR code: {}", err)),
+        None => Err(anyhow::anyhow!("no QR code in {}", path.display())),
+    }
+}
+
+/// Whether `path` looks like a picture that may contain a QR code.
+pub fn is_image(path: &Path) -> bool {
+    image::ImageFormat::from_path(path).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Save `code` with `scale` pixels per module, as a photo of a screen would
+    /// have several.
+    fn save(code: &ColorImage, scale: usize, path: &Path) {
+        let [width, height] = code.size;
+        let image =
+            image::GrayImage::from_fn((width * scale) as u32, (height * scale) as u32, |x, y| {
+                let (x, y) = (x as usize / scale, y as usize / scale);
+                image::Luma([code.pixels[y * width + x].r()])
+            });
+        image.save(path).unwrap();
+    }
+
+    #[test]
+    fn encode_then_decode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ticket.png");
+        let ticket = format!("blob{}", "abcdefghijklmnopqrstuvwxyz234567".repeat(8));
+        save(&encode(&ticket).unwrap(), 4, &path);
+        assert!(is_image(&path));
+        assert_eq!(decode(&path).unwrap(), ticket);
+
+        // a picture without a code
+        image::GrayImage::from_pixel(64, 64, image::Luma([u8::MAX]))
+            .save(&path)
+            .unwrap();
+        assert!(decode(&path).is_err());
+        assert!(!is_image(&dir.path().join("ticket.txt")));
+    }
+}
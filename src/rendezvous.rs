@@ -0,0 +1,559 @@
+//! Short codes like `7-crossword-banana`, which stand for a ticket.
+//!
+//! The sender publishes the ticket of a share under a random [`ShortCode`] at a
+//! [`Rendezvous`], and the receiver looks the code up at the same rendezvous.
+//! A rendezvous is either a directory that both sides can access, see
+//! [`FileRendezvous`], or a server run with [`serve`], see [`TcpRendezvous`].
+//!
+//! A code can only be looked up once. This does not stop guessing: whoever
+//! guesses a code first gets the ticket and uses the code up, and the receiver
+//! then needs the ticket itself. The server limits how many codes each address
+//! can try, which makes guessing slow but not impossible, so a short code is
+//! not a secret. Restrict the share to the node id of the receiver if that
+//! matters.
+use std::{
+    collections::HashMap,
+    fmt,
+    io::ErrorKind,
+    net::IpAddr,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use futures::{future::BoxFuture, FutureExt};
+use iroh_net::ticket::BlobTicket;
+use rand::{seq::SliceRandom, Rng};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{debug, info};
+use url::Url;
+
+use crate::{
+    error::{Result, SendmeError},
+    parse_ticket,
+};
+
+/// Port of a rendezvous server if the URL has none.
+pub const DEFAULT_PORT: u16 = 7474;
+
+/// How long a rendezvous server keeps a code that was not removed.
+const CODE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Longest request a rendezvous server reads, enough for a ticket with many
+/// addresses.
+const MAX_REQUEST_LEN: usize = 4096;
+
+/// Most codes a rendezvous server keeps at once.
+const MAX_CODES: usize = 10_000;
+
+/// Most requests a rendezvous server answers per client address and minute.
+const MAX_REQUESTS_PER_MINUTE: u32 = 30;
+
+/// How long a rendezvous server waits for the next request of a client.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many random codes [`publish`] tries before it gives up.
+const PUBLISH_ATTEMPTS: usize = 8;
+
+/// Words of the codes, 256 of them.
+const WORDS: &str = include_str!("words.txt");
+
+fn words() -> impl Iterator<Item = &'static str> {
+    WORDS.lines()
+}
+
+/// A number and two words, like `7-crossword-banana`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ShortCode {
+    number: u8,
+    words: [&'static str; 2],
+}
+
+impl ShortCode {
+    pub fn random() -> Self {
+        let mut rng = rand::thread_rng();
+        let words = words().collect::<Vec<_>>();
+        Self {
+            number: rng.gen_range(0..100),
+            words: [
+                words.choose(&mut rng).expect("empty word list"),
+                words.choose(&mut rng).expect("empty word list"),
+            ],
+        }
+    }
+}
+
+impl fmt::Display for ShortCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}-{}", self.number, self.words[0], self.words[1])
+    }
+}
+
+impl FromStr for ShortCode {
+    type Err = anyhow::Error;
+
+    /// Parse a code as typed by a user, ignoring case and allowing spaces
+    /// instead of dashes.
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let s = s.trim().to_lowercase();
+        let parts = s
+            .split(|c: char| c == '-' || c.is_whitespace())
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>();
+        let [number, first, second] = parts[..] else {
+            anyhow::bail!("a code has a number and two words");
+        };
+        let number = number.parse::<u8>().ok().filter(|n| *n < 100);
+        let word = |part: &str| words().find(|word| *word == part);
+        match (number, word(first), word(second)) {
+            (Some(number), Some(first), Some(second)) => Ok(Self {
+                number,
+                words: [first, second],
+            }),
+            _ => anyhow::bail!("not a code"),
+        }
+    }
+}
+
+/// Where short codes are published and looked up.
+pub trait Rendezvous: fmt::Debug + Send + Sync {
+    /// Publish `ticket` under `code`. Returns `false` if the code is taken.
+    fn publish<'a>(
+        &'a self,
+        code: &'a ShortCode,
+        ticket: &'a BlobTicket,
+    ) -> BoxFuture<'a, Result<bool>>;
+
+    /// Look up the ticket of `code`, and remove the code.
+    ///
+    /// Fails with [`SendmeError::InvalidTicket`] if the code is unknown.
+    fn resolve<'a>(&'a self, code: &'a ShortCode) -> BoxFuture<'a, Result<BlobTicket>>;
+
+    /// Remove `code`, once its share is stopped. Only removes the code if it
+    /// still stands for `ticket`.
+    fn remove<'a>(
+        &'a self,
+        code: &'a ShortCode,
+        ticket: &'a BlobTicket,
+    ) -> BoxFuture<'a, Result<()>>;
+}
+
+/// Create the rendezvous at `url`, either `file:///some/dir` or
+/// `tcp://host:port`.
+pub fn from_url(url: &Url) -> Result<Arc<dyn Rendezvous>> {
+    match url.scheme() {
+        "file" => {
+            let dir = url
+                .to_file_path()
+                .map_err(|_| SendmeError::other(anyhow::anyhow!("invalid path in {}", url)))?;
+            Ok(Arc::new(FileRendezvous::new(dir)))
+        }
+        "tcp" => {
+            let host = url
+                .host_str()
+                .with_context(|| format!("no host in {}", url))
+                .map_err(SendmeError::other)?;
+            let port = url.port().unwrap_or(DEFAULT_PORT);
+            Ok(Arc::new(TcpRendezvous::new(format!("{}:{}", host, port))))
+        }
+        scheme => Err(SendmeError::other(anyhow::anyhow!(
+            "unsupported rendezvous {}, use file: or tcp:",
+            scheme
+        ))),
+    }
+}
+
+/// Publish `ticket` under a new random code.
+pub async fn publish(rendezvous: &dyn Rendezvous, ticket: &BlobTicket) -> Result<ShortCode> {
+    for _ in 0..PUBLISH_ATTEMPTS {
+        let code = ShortCode::random();
+        if rendezvous.publish(&code, ticket).await? {
+            info!("published {} as {}", ticket.hash(), code);
+            return Ok(code);
+        }
+    }
+    Err(SendmeError::other(anyhow::anyhow!("no free code found")))
+}
+
+/// Parse a ticket or a short code, as entered by a user, into a ticket.
+///
+/// Codes are looked up at `rendezvous`.
+pub async fn resolve(input: &str, rendezvous: Option<&dyn Rendezvous>) -> Result<BlobTicket> {
+    let Ok(code) = input.parse::<ShortCode>() else {
+        return parse_ticket(input);
+    };
+    let rendezvous = rendezvous.ok_or_else(|| {
+        SendmeError::InvalidTicket(anyhow::anyhow!(
+            "{} is a short code, but no rendezvous is set up",
+            code
+        ))
+    })?;
+    rendezvous.resolve(&code).await
+}
+
+fn unknown_code(code: &ShortCode) -> SendmeError {
+    SendmeError::InvalidTicket(anyhow::anyhow!("unknown code {}", code))
+}
+
+/// Codes stored as files in a directory, e.g. on a network share.
+#[derive(Debug, Clone)]
+pub struct FileRendezvous {
+    dir: PathBuf,
+}
+
+impl FileRendezvous {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, code: &ShortCode) -> PathBuf {
+        self.dir.join(code.to_string())
+    }
+}
+
+impl Rendezvous for FileRendezvous {
+    fn publish<'a>(
+        &'a self,
+        code: &'a ShortCode,
+        ticket: &'a BlobTicket,
+    ) -> BoxFuture<'a, Result<bool>> {
+        async move {
+            tokio::fs::create_dir_all(&self.dir)
+                .await
+                .map_err(|err| SendmeError::path(&self.dir, err))?;
+            let path = self.path(code);
+            let res = tokio::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .await;
+            let mut file = match res {
+                Ok(file) => file,
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => return Ok(false),
+                Err(err) => return Err(SendmeError::path(path, err)),
+            };
+            file.write_all(ticket.to_string().as_bytes())
+                .await
+                .map_err(|err| SendmeError::path(&path, err))?;
+            Ok(true)
+        }
+        .boxed()
+    }
+
+    fn resolve<'a>(&'a self, code: &'a ShortCode) -> BoxFuture<'a, Result<BlobTicket>> {
+        async move {
+            let path = self.path(code);
+            let ticket = match tokio::fs::read_to_string(&path).await {
+                Ok(ticket) => ticket,
+                Err(err) if err.kind() == ErrorKind::NotFound => return Err(unknown_code(code)),
+                Err(err) => return Err(SendmeError::path(path, err)),
+            };
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                Err(err) => return Err(SendmeError::path(path, err)),
+            }
+            parse_ticket(&ticket)
+        }
+        .boxed()
+    }
+
+    fn remove<'a>(
+        &'a self,
+        code: &'a ShortCode,
+        ticket: &'a BlobTicket,
+    ) -> BoxFuture<'a, Result<()>> {
+        async move {
+            let path = self.path(code);
+            match tokio::fs::read_to_string(&path).await {
+                Ok(published) if published == ticket.to_string() => {}
+                Ok(_) => return Ok(()),
+                Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+                Err(err) => return Err(SendmeError::path(path, err)),
+            }
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => Ok(()),
+                Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+                Err(err) => Err(SendmeError::path(path, err)),
+            }
+        }
+        .boxed()
+    }
+}
+
+/// Codes stored by a server run with [`serve`].
+///
+/// The protocol is one line per request and reply: `PUT <code> <ticket>`,
+/// `GET <code>` and `DEL <code> <ticket>`, answered with `OK`, `OK <ticket>`,
+/// `TAKEN`, `UNKNOWN` or `ERR <message>`.
+#[derive(Debug, Clone)]
+pub struct TcpRendezvous {
+    addr: String,
+}
+
+impl TcpRendezvous {
+    /// A rendezvous at `addr`, a `host:port` pair.
+    pub fn new(addr: impl Into<String>) -> Self {
+        Self { addr: addr.into() }
+    }
+
+    async fn request(&self, request: String) -> Result<String> {
+        let res = async {
+            let stream = TcpStream::connect(&self.addr).await?;
+            let (read, mut write) = stream.into_split();
+            write.write_all(format!("{}\n", request).as_bytes()).await?;
+            let mut reply = String::new();
+            BufReader::new(read).read_line(&mut reply).await?;
+            anyhow::Ok(reply.trim_end().to_string())
+        };
+        res.await
+            .with_context(|| format!("rendezvous {} failed", self.addr))
+            .map_err(SendmeError::other)
+    }
+}
+
+impl Rendezvous for TcpRendezvous {
+    fn publish<'a>(
+        &'a self,
+        code: &'a ShortCode,
+        ticket: &'a BlobTicket,
+    ) -> BoxFuture<'a, Result<bool>> {
+        async move {
+            match self
+                .request(format!("PUT {} {}", code, ticket))
+                .await?
+                .as_str()
+            {
+                "OK" => Ok(true),
+                "TAKEN" => Ok(false),
+                reply => Err(server_error(reply)),
+            }
+        }
+        .boxed()
+    }
+
+    fn resolve<'a>(&'a self, code: &'a ShortCode) -> BoxFuture<'a, Result<BlobTicket>> {
+        async move {
+            let reply = self.request(format!("GET {}", code)).await?;
+            match reply.split_once(' ') {
+                Some(("OK", ticket)) => parse_ticket(ticket),
+                _ if reply == "UNKNOWN" => Err(unknown_code(code)),
+                _ => Err(server_error(&reply)),
+            }
+        }
+        .boxed()
+    }
+
+    fn remove<'a>(
+        &'a self,
+        code: &'a ShortCode,
+        ticket: &'a BlobTicket,
+    ) -> BoxFuture<'a, Result<()>> {
+        async move {
+            match self
+                .request(format!("DEL {} {}", code, ticket))
+                .await?
+                .as_str()
+            {
+                "OK" => Ok(()),
+                reply => Err(server_error(reply)),
+            }
+        }
+        .boxed()
+    }
+}
+
+fn server_error(reply: &str) -> SendmeError {
+    SendmeError::other(anyhow::anyhow!("rendezvous replied {:?}", reply))
+}
+
+/// State of a rendezvous server.
+#[derive(Debug, Default)]
+struct Server {
+    /// Published tickets by code, with the time they were published.
+    codes: HashMap<String, (String, Instant)>,
+    /// Number of requests of each client address, and when it started counting.
+    requests: HashMap<IpAddr, (Instant, u32)>,
+}
+
+impl Server {
+    /// Count a request from `ip`, and return whether it is within the limit.
+    fn allow(&mut self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        let minute = Duration::from_secs(60);
+        self.requests
+            .retain(|_, (started, _)| now.duration_since(*started) < minute);
+        let (_, count) = self.requests.entry(ip).or_insert((now, 0));
+        *count += 1;
+        *count <= MAX_REQUESTS_PER_MINUTE
+    }
+
+    fn handle(&mut self, request: &str) -> String {
+        self.codes
+            .retain(|_, (_, published)| published.elapsed() < CODE_TTL);
+        let mut parts = request.split(' ');
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some("PUT"), Some(code), Some(ticket), None) => {
+                if self.codes.contains_key(code) {
+                    return "TAKEN".to_string();
+                }
+                if self.codes.len() >= MAX_CODES {
+                    return "ERR too many codes".to_string();
+                }
+                self.codes
+                    .insert(code.to_string(), (ticket.to_string(), Instant::now()));
+                "OK".to_string()
+            }
+            (Some("GET"), Some(code), None, None) => match self.codes.remove(code) {
+                Some((ticket, _)) => format!("OK {}", ticket),
+                None => "UNKNOWN".to_string(),
+            },
+            (Some("DEL"), Some(code), Some(ticket), None) => {
+                // only the sender knows the ticket of a code that was not looked up
+                if self
+                    .codes
+                    .get(code)
+                    .is_some_and(|(published, _)| published == ticket)
+                {
+                    self.codes.remove(code);
+                }
+                "OK".to_string()
+            }
+            _ => "ERR invalid request".to_string(),
+        }
+    }
+}
+
+/// Run a rendezvous server for [`TcpRendezvous`] clients on `listener`.
+///
+/// Codes are only kept in memory, for at most a day, and each client address
+/// gets a limited number of requests per minute.
+pub async fn serve(listener: TcpListener) -> Result<()> {
+    let server = Arc::new(Mutex::new(Server::default()));
+    loop {
+        let (stream, addr) = listener.accept().await.map_err(SendmeError::other)?;
+        let server = server.clone();
+        tokio::spawn(async move {
+            if let Err(err) = handle_client(stream, addr.ip(), server).await {
+                debug!("rendezvous client {} failed: {:?}", addr, err);
+            }
+        });
+    }
+}
+
+async fn handle_client(
+    stream: TcpStream,
+    ip: IpAddr,
+    server: Arc<Mutex<Server>>,
+) -> anyhow::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut read = BufReader::new(read);
+    loop {
+        let mut request = Vec::new();
+        let mut limited = (&mut read).take(MAX_REQUEST_LEN as u64 + 1);
+        let len = tokio::time::timeout(REQUEST_TIMEOUT, limited.read_until(b'\n', &mut request))
+            .await??;
+        if len == 0 {
+            return Ok(());
+        }
+        if request.len() > MAX_REQUEST_LEN {
+            write.write_all(b"ERR request too long\n").await?;
+            return Ok(());
+        }
+        let allowed = server.lock().unwrap().allow(ip);
+        if !allowed {
+            write.write_all(b"ERR too many requests\n").await?;
+            return Ok(());
+        }
+        let reply = match std::str::from_utf8(&request) {
+            Ok(request) => server.lock().unwrap().handle(request.trim_end()),
+            Err(_) => "ERR invalid request".to_string(),
+        };
+        write.write_all(format!("{}\n", reply).as_bytes()).await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh_bytes::{BlobFormat, Hash};
+    use iroh_net::{key::SecretKey, NodeAddr};
+
+    use super::*;
+
+    fn random_ticket() -> BlobTicket {
+        let addr = NodeAddr::new(SecretKey::generate().public());
+        let hash = Hash::new(rand::random::<[u8; 32]>());
+        BlobTicket::new(addr, hash, BlobFormat::HashSeq).unwrap()
+    }
+
+    async fn publish_resolve_remove(rendezvous: &dyn Rendezvous) {
+        let code = ShortCode::random();
+        let ticket = random_ticket();
+        assert!(rendezvous.publish(&code, &ticket).await.unwrap());
+        assert!(!rendezvous.publish(&code, &random_ticket()).await.unwrap());
+        let resolved = rendezvous.resolve(&code).await.unwrap();
+        assert_eq!(resolved.to_string(), ticket.to_string());
+        // codes are single use
+        let res = rendezvous.resolve(&code).await;
+        assert!(matches!(res, Err(SendmeError::InvalidTicket(_))));
+
+        // only the ticket a code stands for removes it
+        let code = ShortCode::random();
+        assert!(rendezvous.publish(&code, &ticket).await.unwrap());
+        rendezvous.remove(&code, &random_ticket()).await.unwrap();
+        rendezvous.remove(&code, &ticket).await.unwrap();
+        let res = rendezvous.resolve(&code).await;
+        assert!(matches!(res, Err(SendmeError::InvalidTicket(_))));
+        // removing an unknown code is fine
+        rendezvous.remove(&code, &ticket).await.unwrap();
+    }
+
+    #[test]
+    fn short_code_roundtrip() {
+        for _ in 0..100 {
+            let code = ShortCode::random();
+            assert_eq!(code.to_string().parse::<ShortCode>().unwrap(), code);
+        }
+        let code = " 7 Crossword-BANANA ".parse::<ShortCode>().unwrap();
+        assert_eq!(code.to_string(), "7-crossword-banana");
+        assert!("100-crossword-banana".parse::<ShortCode>().is_err());
+        assert!("7-crossword".parse::<ShortCode>().is_err());
+        assert!("7-crossword-notaword".parse::<ShortCode>().is_err());
+    }
+
+    #[tokio::test]
+    async fn file_rendezvous() {
+        let dir = tempfile::tempdir().unwrap();
+        let rendezvous = FileRendezvous::new(dir.path().join("codes"));
+        publish_resolve_remove(&rendezvous).await;
+    }
+
+    #[tokio::test]
+    async fn tcp_rendezvous() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(listener));
+        let rendezvous = TcpRendezvous::new(addr.to_string());
+        publish_resolve_remove(&rendezvous).await;
+
+        // one byte too long with the newline, so the server reads all of it
+        let code = "a".repeat(MAX_REQUEST_LEN - "GET ".len());
+        let res = rendezvous.request(format!("GET {}", code)).await;
+        assert_eq!(res.unwrap(), "ERR request too long");
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn codes_need_a_rendezvous() {
+        let res = resolve("7-crossword-banana", None).await;
+        assert!(matches!(res, Err(SendmeError::InvalidTicket(_))));
+        let ticket = random_ticket();
+        let resolved = resolve(&ticket.to_string(), None).await.unwrap();
+        assert_eq!(resolved.to_string(), ticket.to_string());
+    }
+}
@@ -0,0 +1,360 @@
+//! The settings window.
+use std::sync::Mutex;
+
+use eframe::egui::{self, RichText};
+use iroh_net::key::PublicKey;
+use sendme::{identity, RelayMode};
+use url::Url;
+
+use crate::{config::Config, worker::SharedState, TEXT_COLOR};
+
+#[derive(Debug)]
+pub struct Settings {
+    open: bool,
+    /// The saved settings.
+    config: Config,
+    /// Node id of the current key, loaded when the window is opened.
+    node_id: Option<PublicKey>,
+    /// The secret key, while the user has it revealed.
+    secret: Option<String>,
+    /// Rotating was clicked and waits for confirmation.
+    confirm_rotate: bool,
+    /// Custom relay servers as edited by the user, one URL per line.
+    relay_urls: String,
+    /// Custom relay servers were chosen, but not applied yet.
+    editing_relays: bool,
+    /// The device name as edited by the user.
+    device_name: String,
+    /// The rendezvous URL as edited by the user.
+    rendezvous: String,
+}
+
+impl Settings {
+    pub fn new(config: Config) -> Self {
+        Self {
+            open: false,
+            config,
+            node_id: None,
+            secret: None,
+            confirm_rotate: false,
+            relay_urls: String::new(),
+            editing_relays: false,
+            device_name: String::new(),
+            rendezvous: String::new(),
+        }
+    }
+
+    pub fn open(&mut self) {
+        self.open = true;
+        self.node_id = None;
+        self.secret = None;
+        self.confirm_rotate = false;
+        self.editing_relays = false;
+        self.device_name = self.config.device_name();
+        self.rendezvous = self
+            .config
+            .rendezvous
+            .as_ref()
+            .map(|url| url.to_string())
+            .unwrap_or_default();
+        self.relay_urls = match &self.config.relay {
+            RelayMode::Custom(urls) => urls
+                .iter()
+                .map(|url| url.to_string())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            _ => String::new(),
+        };
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, state: &Mutex<SharedState>) {
+        if !self.open {
+            return;
+        }
+        if self.node_id.is_none() {
+            match identity::node_id() {
+                Ok(node_id) => self.node_id = Some(node_id),
+                Err(err) => {
+                    report(state, err, "failed to load the secret key");
+                    self.open = false;
+                    return;
+                }
+            }
+        }
+        let mut open = self.open;
+        egui::Window::new("Settings")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                self.show_identity(ui, state);
+                ui.separator();
+                self.show_device_name(ui, state);
+                ui.separator();
+                self.show_relay(ui, state);
+                ui.separator();
+                self.show_rendezvous(ui, state);
+            });
+        self.open &= open;
+    }
+
+    fn show_identity(&mut self, ui: &mut egui::Ui, state: &Mutex<SharedState>) {
+        ui.label(RichText::new("Identity").strong());
+        ui.label(RichText::new("Peers recognize this device by its node id.").color(TEXT_COLOR));
+        if identity::is_overridden() {
+            ui.label(
+                RichText::new(format!(
+                    "Set by {}. Importing or rotating changes the stored key, \
+                     which is used once the variable is unset.",
+                    identity::SECRET_ENV
+                ))
+                .color(TEXT_COLOR),
+            );
+        }
+        ui.add_space(5.);
+
+        if let Some(node_id) = self.node_id {
+            ui.horizontal(|ui| {
+                ui.label(RichText::new(node_id.to_string()).monospace());
+                if ui.small_button("Copy").clicked() {
+                    ui.output_mut(|o| o.copied_text = node_id.to_string());
+                }
+            });
+        }
+
+        if let Some(secret) = self.secret.clone() {
+            ui.add_space(5.);
+            ui.label(
+                RichText::new("Anyone with the secret key can act as this device.")
+                    .color(TEXT_COLOR),
+            );
+            ui.horizontal(|ui| {
+                ui.label(RichText::new(&secret).monospace());
+                if ui.small_button("Copy").clicked() {
+                    ui.output_mut(|o| o.copied_text = secret);
+                }
+            });
+        }
+        ui.add_space(5.);
+
+        ui.horizontal(|ui| {
+            if self.secret.is_some() {
+                if ui.button("Hide secret key").clicked() {
+                    self.secret = None;
+                }
+            } else if ui.button("Show secret key").clicked() {
+                match identity::export() {
+                    Ok(secret) => self.secret = Some(secret),
+                    Err(err) => report(state, err, "failed to load the secret key"),
+                }
+            }
+
+            if ui.button("Export…").clicked() {
+                if let Some(path) = rfd::FileDialog::new()
+                    .set_file_name("sendme-secret-key")
+                    .save_file()
+                {
+                    if let Err(err) = identity::export_to(&path) {
+                        report(state, err, "failed to export the secret key");
+                    }
+                }
+            }
+
+            if ui.button("Import…").clicked() {
+                if let Some(path) = rfd::FileDialog::new().pick_file() {
+                    match identity::import_from(&path) {
+                        Ok(node_id) => self.replaced(node_id),
+                        Err(err) => report(state, err, "failed to import the secret key"),
+                    }
+                }
+            }
+
+            if !self.confirm_rotate && ui.button("Rotate…").clicked() {
+                self.confirm_rotate = true;
+            }
+        });
+
+        if self.confirm_rotate {
+            ui.add_space(5.);
+            ui.label("Peers will no longer recognize this device. Shares that are running keep the old key.");
+            ui.horizontal(|ui| {
+                if ui.button("Rotate").clicked() {
+                    match identity::rotate() {
+                        Ok(node_id) => self.replaced(node_id),
+                        Err(err) => report(state, err, "failed to rotate the secret key"),
+                    }
+                    self.confirm_rotate = false;
+                }
+                if ui.button("Cancel").clicked() {
+                    self.confirm_rotate = false;
+                }
+            });
+        }
+    }
+
+    /// The stored key was replaced.
+    fn replaced(&mut self, node_id: PublicKey) {
+        self.node_id = Some(node_id);
+        self.secret = None;
+    }
+
+    fn show_device_name(&mut self, ui: &mut egui::Ui, state: &Mutex<SharedState>) {
+        ui.label(RichText::new("Device name").strong());
+        ui.label(RichText::new("Shown to devices on the same network.").color(TEXT_COLOR));
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.device_name);
+            let name = self.device_name.trim();
+            if ui
+                .add_enabled(!name.is_empty(), egui::Button::new("Apply"))
+                .clicked()
+            {
+                self.config.device_name = Some(name.to_string());
+                {
+                    let mut state = state.lock().unwrap();
+                    state.device_name = name.to_string();
+                    if let Some(discovery) = &state.discovery {
+                        discovery.set_name(name.to_string());
+                    }
+                }
+                if let Err(err) = self.config.save() {
+                    report(state, err, "failed to save the settings");
+                }
+            }
+        });
+        let mut announce = !self.config.hidden;
+        if ui
+            .checkbox(&mut announce, "Show this device to others nearby")
+            .changed()
+        {
+            self.config.hidden = !announce;
+            {
+                let mut state = state.lock().unwrap();
+                state.hidden = !announce;
+                if let Some(discovery) = &state.discovery {
+                    discovery.set_announce(announce);
+                }
+            }
+            if let Err(err) = self.config.save() {
+                report(state, err, "failed to save the settings");
+            }
+        }
+    }
+
+    fn show_relay(&mut self, ui: &mut egui::Ui, state: &Mutex<SharedState>) {
+        ui.label(RichText::new("Relay servers").strong());
+        ui.label(
+            RichText::new("Connect peers that can not reach each other directly.")
+                .color(TEXT_COLOR),
+        );
+        let custom = self.editing_relays || matches!(self.config.relay, RelayMode::Custom(_));
+        if ui
+            .radio(
+                !custom && self.config.relay == RelayMode::Default,
+                "Public relay servers",
+            )
+            .clicked()
+        {
+            self.set_relay(RelayMode::Default, state);
+        }
+        if ui.radio(custom, "Custom relay servers").clicked() {
+            self.editing_relays = true;
+        }
+        if custom {
+            ui.label(
+                RichText::new("One URL per line, e.g. https://relay.example.com").color(TEXT_COLOR),
+            );
+            ui.text_edit_multiline(&mut self.relay_urls);
+            if ui.button("Apply").clicked() {
+                match parse_urls(&self.relay_urls) {
+                    Ok(urls) => self.set_relay(RelayMode::Custom(urls), state),
+                    Err(err) => report(state, err, "invalid relay servers"),
+                }
+            }
+        }
+        if ui
+            .radio(
+                !custom && self.config.relay == RelayMode::Disabled,
+                "None, peers need to be on the same network",
+            )
+            .clicked()
+        {
+            self.set_relay(RelayMode::Disabled, state);
+        }
+        ui.label(RichText::new("Applies to new shares and downloads.").color(TEXT_COLOR));
+    }
+
+    fn show_rendezvous(&mut self, ui: &mut egui::Ui, state: &Mutex<SharedState>) {
+        ui.label(RichText::new("Short codes").strong());
+        ui.label(
+            RichText::new(
+                "Publish tickets as codes like 7-crossword-banana, e.g. at \
+                 tcp://codes.example.com:7474 or file:///mnt/shared/codes. \
+                 Leave empty to only use tickets.",
+            )
+            .color(TEXT_COLOR),
+        );
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.rendezvous);
+            if ui.button("Apply").clicked() {
+                let text = self.rendezvous.trim();
+                let url = match text {
+                    "" => None,
+                    text => match Url::parse(text) {
+                        Ok(url) => Some(url),
+                        Err(err) => {
+                            report(state, err, "invalid rendezvous");
+                            return;
+                        }
+                    },
+                };
+                let config = Config {
+                    rendezvous: url,
+                    ..self.config.clone()
+                };
+                match config.rendezvous() {
+                    Ok(rendezvous) => {
+                        self.config = config;
+                        state.lock().unwrap().rendezvous = rendezvous;
+                        if let Err(err) = self.config.save() {
+                            report(state, err, "failed to save the settings");
+                        }
+                    }
+                    Err(err) => report(state, err, "invalid rendezvous"),
+                }
+            }
+        });
+        ui.label(RichText::new("Applies to new shares.").color(TEXT_COLOR));
+    }
+
+    /// Use `relay` for new shares and downloads, and save it.
+    fn set_relay(&mut self, relay: RelayMode, state: &Mutex<SharedState>) {
+        self.editing_relays = false;
+        if relay == self.config.relay {
+            return;
+        }
+        self.config.relay = relay.clone();
+        state.lock().unwrap().relay = relay;
+        if let Err(err) = self.config.save() {
+            report(state, err, "failed to save the settings");
+        }
+    }
+}
+
+fn parse_urls(text: &str) -> anyhow::Result<Vec<Url>> {
+    let urls = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(Url::parse)
+        .collect::<Result<Vec<_>, _>>()?;
+    anyhow::ensure!(!urls.is_empty(), "no relay server given");
+    Ok(urls)
+}
+
+fn report(state: &Mutex<SharedState>, err: impl Into<anyhow::Error>, context: &'static str) {
+    state
+        .lock()
+        .unwrap()
+        .errors
+        .push(err.into().context(context));
+}
@@ -0,0 +1,124 @@
+//! The file tree of a collection, for choosing which files to download.
+use std::collections::BTreeMap;
+
+/// The files of a collection, arranged as a directory tree.
+///
+/// Collection names use `/` as the separator, e.g. `photos/2023/beach.jpg`.
+#[derive(Debug, Clone, Default)]
+pub struct FileTree {
+    pub dirs: BTreeMap<String, FileTree>,
+    /// The files directly in this directory.
+    pub files: Vec<FileEntry>,
+    /// Total size of all files below this directory.
+    pub size: u64,
+    /// Number of files below this directory.
+    pub count: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct FileEntry {
+    pub name: String,
+    /// Position of the file in the collection.
+    pub index: usize,
+    pub size: u64,
+}
+
+impl FileTree {
+    /// Build a tree from `(name, size)` pairs, in collection order.
+    pub fn new<'a>(files: impl IntoIterator<Item = &'a (String, u64)>) -> Self {
+        let mut tree = FileTree::default();
+        for (index, (name, size)) in files.into_iter().enumerate() {
+            tree.insert(name, index, *size);
+        }
+        tree
+    }
+
+    fn insert(&mut self, name: &str, index: usize, size: u64) {
+        self.size += size;
+        self.count += 1;
+        match name.split_once('/') {
+            Some((dir, rest)) => self
+                .dirs
+                .entry(dir.to_string())
+                .or_default()
+                .insert(rest, index, size),
+            None => self.files.push(FileEntry {
+                name: name.to_string(),
+                index,
+                size,
+            }),
+        }
+    }
+
+    /// Positions of all files below this directory.
+    pub fn indices(&self) -> Vec<usize> {
+        let mut indices = self.files.iter().map(|file| file.index).collect::<Vec<_>>();
+        for dir in self.dirs.values() {
+            indices.extend(dir.indices());
+        }
+        indices
+    }
+
+    /// Number and total size of the selected files below this directory.
+    pub fn selected(&self, selected: &[bool]) -> (u64, u64) {
+        let mut res = self
+            .files
+            .iter()
+            .filter(|file| selected[file.index])
+            .fold((0, 0), |(count, size), file| (count + 1, size + file.size));
+        for dir in self.dirs.values() {
+            let (count, size) = dir.selected(selected);
+            res.0 += count;
+            res.1 += size;
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files() -> Vec<(String, u64)> {
+        [
+            ("readme.txt", 10),
+            ("photos/2023/beach.jpg", 200),
+            ("photos/2023/city.jpg", 300),
+            ("photos/cat.jpg", 400),
+            ("notes.txt", 20),
+        ]
+        .into_iter()
+        .map(|(name, size)| (name.to_string(), size))
+        .collect()
+    }
+
+    #[test]
+    fn indices_of_directories() {
+        let tree = FileTree::new(&files());
+        assert_eq!((tree.count, tree.size), (5, 930));
+        let mut indices = tree.indices();
+        indices.sort();
+        assert_eq!(indices, [0, 1, 2, 3, 4]);
+
+        let photos = &tree.dirs["photos"];
+        assert_eq!((photos.count, photos.size), (3, 900));
+        let mut indices = photos.indices();
+        indices.sort();
+        assert_eq!(indices, [1, 2, 3]);
+        assert_eq!(photos.dirs["2023"].indices(), [1, 2]);
+    }
+
+    #[test]
+    fn selected_counts_and_sizes() {
+        let tree = FileTree::new(&files());
+        assert_eq!(tree.selected(&[true; 5]), (5, 930));
+        assert_eq!(tree.selected(&[false; 5]), (0, 0));
+        let selected = [false, true, false, true, true];
+        assert_eq!(tree.selected(&selected), (3, 620));
+        assert_eq!(tree.dirs["photos"].selected(&selected), (2, 600));
+        assert_eq!(
+            tree.dirs["photos"].dirs["2023"].selected(&selected),
+            (1, 200)
+        );
+    }
+}
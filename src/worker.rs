@@ -0,0 +1,724 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use eframe::egui;
+use iroh_bytes::provider::Event;
+use iroh_net::{ticket::BlobTicket, NodeAddr};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use sendme::{
+    progress::TransferProgress, Allowlist, Rendezvous, SendmeError, SharePolicy, ShortCode,
+};
+
+use crate::{share_name, tree::FileTree};
+
+/// Identifies a job, and the share it turns into once it is ready.
+pub type JobId = u64;
+
+#[derive(Debug, Default)]
+pub struct SharedState {
+    /// Shares that are being prepared and downloads that are running.
+    pub jobs: BTreeMap<JobId, Job>,
+    pub shares: Vec<ActiveShare>,
+    /// Stats of the selected paths, `None` if the path could not be read.
+    ///
+    /// Paths that are still being scanned have no entry.
+    pub path_stats: HashMap<PathBuf, Option<sendme::PathStats>>,
+    /// The ticket that is being previewed before downloading it.
+    pub preview: Option<TicketPreview>,
+    pub errors: Vec<anyhow::Error>,
+    /// Relay setting for new shares and downloads.
+    pub relay: sendme::RelayMode,
+    /// Name this device is shown with to others.
+    pub device_name: String,
+    /// Whether this device is hidden from other devices on the local network.
+    pub hidden: bool,
+    /// Receives offers from other devices, once it is listening.
+    pub inbox: Option<sendme::Inbox>,
+    /// Ticket of the inbox, for devices that are not nearby.
+    pub inbox_ticket: Option<sendme::InboxTicket>,
+    /// Offers waiting for the user to accept or reject them.
+    pub offers: Vec<sendme::Offer>,
+    /// Announces this device, `None` if local discovery is not available.
+    pub discovery: Option<sendme::Discovery>,
+    /// Other devices running the app on the local network.
+    pub nearby: Vec<sendme::NearbyDevice>,
+    /// Where short codes of new shares are published, `None` to not use codes.
+    pub rendezvous: Option<Arc<dyn Rendezvous>>,
+}
+
+/// The contents of a ticket, shown before the user commits to a download.
+#[derive(Debug)]
+pub struct TicketPreview {
+    pub id: JobId,
+    pub ticket: BlobTicket,
+    /// The files of the collection, `None` while they are being fetched.
+    pub files: Option<FileTree>,
+    /// Whether each file of the collection is selected for download.
+    pub selected: Vec<bool>,
+    pub cancel: CancellationToken,
+}
+
+impl TicketPreview {
+    /// The positions of the selected files, `None` if all of them are selected.
+    pub fn selection(&self) -> Option<Vec<usize>> {
+        if self.selected.iter().all(|selected| *selected) {
+            return None;
+        }
+        let selection = self
+            .selected
+            .iter()
+            .enumerate()
+            .filter(|(_, selected)| **selected)
+            .map(|(i, _)| i)
+            .collect();
+        Some(selection)
+    }
+}
+
+/// A share that is being prepared or a download that is running.
+#[derive(Debug)]
+pub struct Job {
+    pub kind: JobKind,
+    pub name: String,
+    pub progress: TransferProgress,
+    pub cancel: CancellationToken,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobKind {
+    Share,
+    Download,
+}
+
+/// A share that is currently being served.
+#[derive(Debug)]
+pub struct ActiveShare {
+    pub id: JobId,
+    pub name: String,
+    pub share: sendme::Share,
+    pub started: Instant,
+    /// Peers that connected to this share, by connection id.
+    pub peers: BTreeMap<u64, PeerProgress>,
+    /// The device this share was pushed to, and whether it accepted yet.
+    pub offer: Option<(String, OfferStatus)>,
+    /// The short code of the ticket, once it is published.
+    pub code: Option<ShortCode>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OfferStatus {
+    Waiting,
+    Accepted,
+}
+
+impl OfferStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            OfferStatus::Waiting => "waiting for",
+            OfferStatus::Accepted => "accepted by",
+        }
+    }
+}
+
+/// What a single peer is doing with a share.
+#[derive(Debug, Default)]
+pub struct PeerProgress {
+    /// Number of requests made by the peer.
+    pub requests: u64,
+    /// Number of blobs sent to the peer.
+    pub blobs_sent: u64,
+    /// Number of blobs in the collection being sent, once known.
+    pub blobs_total: Option<u64>,
+    /// Bytes of completed blobs sent to the peer.
+    pub bytes_sent: u64,
+    pub status: PeerStatus,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PeerStatus {
+    #[default]
+    Connected,
+    Transferring,
+    Done,
+    Aborted,
+}
+
+impl PeerStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            PeerStatus::Connected => "connected",
+            PeerStatus::Transferring => "downloading",
+            PeerStatus::Done => "done",
+            PeerStatus::Aborted => "aborted",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum WorkerMessage {
+    /// Share the paths with the receivers on the allowlist, until the policy
+    /// ends the share.
+    Share(Vec<PathBuf>, Allowlist, SharePolicy),
+    /// Share the paths and offer them to the inbox at the address, which
+    /// belongs to the named device.
+    Push(Vec<PathBuf>, Allowlist, SharePolicy, NodeAddr, String),
+    StopShare(JobId),
+    /// Fetch the list of files of a ticket.
+    Preview(String),
+    /// Download a ticket, optionally only the files at the given positions.
+    Get(BlobTicket, PathBuf, Option<Vec<usize>>),
+}
+
+/// Start the worker thread and return the channel to send it work.
+///
+/// Every message is handled by its own task, so any number of shares and
+/// downloads can run at the same time. The worker also runs the inbox and
+/// announces it to nearby devices.
+pub fn spawn(
+    ctx: egui::Context,
+    shared_state: Arc<Mutex<SharedState>>,
+) -> flume::Sender<WorkerMessage> {
+    let (s, r) = flume::unbounded();
+
+    std::thread::spawn(move || {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            tokio::spawn(inbox_job(shared_state.clone(), ctx.clone()));
+            let mut next_id = 0;
+            while let Ok(msg) = r.recv_async().await {
+                let ctx = ctx.clone();
+                let shared_state = shared_state.clone();
+                match msg {
+                    WorkerMessage::Share(paths, allowlist, policy) => {
+                        let id = next_id;
+                        next_id += 1;
+                        tokio::spawn(share_job(
+                            id,
+                            paths,
+                            allowlist,
+                            policy,
+                            None,
+                            shared_state,
+                            ctx,
+                        ));
+                    }
+                    WorkerMessage::Push(paths, allowlist, policy, addr, name) => {
+                        let id = next_id;
+                        next_id += 1;
+                        let to = Some((addr, name));
+                        tokio::spawn(share_job(
+                            id,
+                            paths,
+                            allowlist,
+                            policy,
+                            to,
+                            shared_state,
+                            ctx,
+                        ));
+                    }
+                    WorkerMessage::StopShare(id) => {
+                        tokio::spawn(stop_share(id, shared_state, ctx));
+                    }
+                    WorkerMessage::Preview(ticket) => {
+                        let id = next_id;
+                        next_id += 1;
+                        tokio::spawn(preview_job(id, ticket, shared_state, ctx));
+                    }
+                    WorkerMessage::Get(ticket, target, selection) => {
+                        let id = next_id;
+                        next_id += 1;
+                        tokio::spawn(get_job(id, ticket, target, selection, shared_state, ctx));
+                    }
+                }
+            }
+        });
+    });
+
+    s
+}
+
+/// Share `paths` with the receivers on `allowlist` until `policy` ends the
+/// share, and offer it to the inbox `to` if given.
+async fn share_job(
+    id: JobId,
+    paths: Vec<PathBuf>,
+    allowlist: Allowlist,
+    policy: SharePolicy,
+    to: Option<(NodeAddr, String)>,
+    shared_state: Arc<Mutex<SharedState>>,
+    ctx: egui::Context,
+) {
+    for path in &paths {
+        info!("sharing: {}", path.display());
+    }
+    let name = share_name(&paths);
+    let cancel = CancellationToken::new();
+    shared_state.lock().unwrap().jobs.insert(
+        id,
+        Job {
+            kind: JobKind::Share,
+            name: name.clone(),
+            progress: TransferProgress::new(0),
+            cancel: cancel.clone(),
+        },
+    );
+    ctx.request_repaint();
+
+    // total size, for the import progress
+    let total = tokio::task::spawn_blocking({
+        let paths = paths.clone();
+        move || {
+            paths
+                .iter()
+                .filter_map(|path| sendme::path_stats(path).ok())
+                .map(|stats| stats.size)
+                .sum::<u64>()
+        }
+    })
+    .await
+    .unwrap_or_default();
+    if let Some(job) = shared_state.lock().unwrap().jobs.get_mut(&id) {
+        job.progress.set_total(total);
+    }
+
+    // provider events, buffered until the share is in the shared state
+    let (events_send, events_recv) = flume::unbounded();
+
+    // import progress
+    let (send, recv) = flume::bounded(32);
+    tokio::spawn({
+        let shared_state = shared_state.clone();
+        let ctx = ctx.clone();
+        async move {
+            while let Ok(ev) = recv.recv_async().await {
+                if let Some(job) = shared_state.lock().unwrap().jobs.get_mut(&id) {
+                    job.progress.on_import(ev);
+                }
+                ctx.request_repaint();
+            }
+        }
+    });
+
+    let relay = shared_state.lock().unwrap().relay.clone();
+    let res = sendme::provide(paths, relay, allowlist, policy, send, events_send, cancel).await;
+    let mut state = shared_state.lock().unwrap();
+    state.jobs.remove(&id);
+    match res {
+        Ok(share) => {
+            let offer = to.map(|(addr, to_name)| {
+                info!("offering {} to {}", share.ticket, addr.node_id);
+                let offer = share.offer(addr, state.device_name.clone());
+                tokio::spawn(offer_job(
+                    id,
+                    offer,
+                    to_name.clone(),
+                    shared_state.clone(),
+                    ctx.clone(),
+                ));
+                (to_name, OfferStatus::Waiting)
+            });
+            tokio::spawn({
+                let ended = share.ended();
+                let shared_state = shared_state.clone();
+                let ctx = ctx.clone();
+                async move {
+                    ended.await;
+                    // a no-op if the user stopped the share
+                    stop_share(id, shared_state, ctx).await;
+                }
+            });
+            if let Some(rendezvous) = state.rendezvous.clone() {
+                tokio::spawn(code_job(
+                    id,
+                    share.ticket.clone(),
+                    rendezvous,
+                    shared_state.clone(),
+                    ctx.clone(),
+                ));
+            }
+            state.shares.push(ActiveShare {
+                id,
+                name,
+                share,
+                started: Instant::now(),
+                peers: BTreeMap::new(),
+                offer,
+                code: None,
+            });
+            tokio::spawn(track_share_events(
+                id,
+                events_recv,
+                shared_state.clone(),
+                ctx.clone(),
+            ));
+        }
+        Err(SendmeError::Cancelled) => {
+            info!("sharing cancelled");
+        }
+        Err(err) => {
+            error!("failed: {:?}", err);
+            state
+                .errors
+                .push(anyhow::Error::new(err).context("sharing"));
+        }
+    }
+    ctx.request_repaint();
+}
+
+/// Wait for the user of an inbox to decide about the share with the given `id`.
+///
+/// The share is stopped if they reject it.
+async fn offer_job(
+    id: JobId,
+    offer: impl Future<Output = sendme::Result<bool>>,
+    to_name: String,
+    shared_state: Arc<Mutex<SharedState>>,
+    ctx: egui::Context,
+) {
+    let res = offer.await;
+    if let Ok(true) = res {
+        let mut state = shared_state.lock().unwrap();
+        if let Some(share) = state.shares.iter_mut().find(|share| share.id == id) {
+            share.offer = Some((to_name, OfferStatus::Accepted));
+        }
+        ctx.request_repaint();
+        return;
+    }
+    let err = match res {
+        Ok(_) => anyhow::anyhow!("{} declined the files", to_name),
+        Err(err) => anyhow::Error::new(err).context(format!("sending to {}", to_name)),
+    };
+    warn!("offer failed: {:?}", err);
+    shared_state.lock().unwrap().errors.push(err);
+    stop_share(id, shared_state, ctx).await;
+}
+
+/// Publish a short code for the ticket of the share with the given `id`.
+async fn code_job(
+    id: JobId,
+    ticket: BlobTicket,
+    rendezvous: Arc<dyn Rendezvous>,
+    shared_state: Arc<Mutex<SharedState>>,
+    ctx: egui::Context,
+) {
+    let code = match sendme::rendezvous::publish(rendezvous.as_ref(), &ticket).await {
+        Ok(code) => code,
+        Err(err) => {
+            warn!("failed to publish code: {:?}", err);
+            shared_state
+                .lock()
+                .unwrap()
+                .errors
+                .push(anyhow::Error::new(err).context("publishing short code"));
+            ctx.request_repaint();
+            return;
+        }
+    };
+    let published = {
+        let mut state = shared_state.lock().unwrap();
+        match state.shares.iter_mut().find(|share| share.id == id) {
+            Some(share) => {
+                share.code = Some(code.clone());
+                true
+            }
+            None => false,
+        }
+    };
+    if published {
+        ctx.request_repaint();
+    } else {
+        // stopped while publishing
+        rendezvous.remove(&code, &ticket).await.ok();
+    }
+}
+
+async fn stop_share(id: JobId, shared_state: Arc<Mutex<SharedState>>, ctx: egui::Context) {
+    let share = {
+        let mut state = shared_state.lock().unwrap();
+        let Some(pos) = state.shares.iter().position(|share| share.id == id) else {
+            return;
+        };
+        state.shares.remove(pos)
+    };
+    ctx.request_repaint();
+
+    info!("stopping share: {}", share.share.ticket);
+    if let Some(code) = &share.code {
+        let rendezvous = shared_state.lock().unwrap().rendezvous.clone();
+        if let Some(rendezvous) = rendezvous {
+            if let Err(err) = rendezvous.remove(code, &share.share.ticket).await {
+                warn!("failed to remove code {}: {:?}", code, err);
+            }
+        }
+    }
+    if let Err(err) = share.share.stop().await {
+        error!("failed: {:?}", err);
+        shared_state
+            .lock()
+            .unwrap()
+            .errors
+            .push(anyhow::Error::new(err).context("stopping share"));
+        ctx.request_repaint();
+    }
+}
+
+async fn preview_job(
+    id: JobId,
+    ticket: String,
+    shared_state: Arc<Mutex<SharedState>>,
+    ctx: egui::Context,
+) {
+    let rendezvous = shared_state.lock().unwrap().rendezvous.clone();
+    let ticket = match sendme::rendezvous::resolve(&ticket, rendezvous.as_deref()).await {
+        Ok(ticket) => ticket,
+        Err(err) => {
+            warn!("invalid ticket: {:?}", err);
+            shared_state
+                .lock()
+                .unwrap()
+                .errors
+                .push(anyhow::Error::new(err).context("parsing ticket"));
+            ctx.request_repaint();
+            return;
+        }
+    };
+    info!("previewing: {}", ticket);
+
+    let cancel = CancellationToken::new();
+    let relay = {
+        let mut state = shared_state.lock().unwrap();
+        state.preview = Some(TicketPreview {
+            id,
+            ticket: ticket.clone(),
+            files: None,
+            selected: Vec::new(),
+            cancel: cancel.clone(),
+        });
+        state.relay.clone()
+    };
+    ctx.request_repaint();
+
+    let res = tokio::select! {
+        res = sendme::preview(&ticket, &relay) => res,
+        _ = cancel.cancelled() => return,
+    };
+    let mut state = shared_state.lock().unwrap();
+    // the preview may have been closed or replaced in the meantime
+    let Some(preview) = state.preview.as_mut().filter(|preview| preview.id == id) else {
+        return;
+    };
+    match res {
+        Ok(res) => {
+            preview.selected = vec![true; res.files.len()];
+            preview.files = Some(FileTree::new(&res.files));
+        }
+        Err(err) => {
+            error!("failed: {:?}", err);
+            state.preview = None;
+            state
+                .errors
+                .push(anyhow::Error::new(err).context("preview"));
+        }
+    }
+    ctx.request_repaint();
+}
+
+async fn get_job(
+    id: JobId,
+    ticket: BlobTicket,
+    target: PathBuf,
+    selection: Option<Vec<usize>>,
+    shared_state: Arc<Mutex<SharedState>>,
+    ctx: egui::Context,
+) {
+    info!("getting: {}", ticket);
+    let name = format!("{} to {}", ticket.hash().to_hex(), target.display());
+
+    // download progress
+    let (send, recv) = flume::bounded(32);
+    let relay = shared_state.lock().unwrap().relay.clone();
+    let download = sendme::Download::start(ticket, target, selection, relay, send);
+    shared_state.lock().unwrap().jobs.insert(
+        id,
+        Job {
+            kind: JobKind::Download,
+            name,
+            progress: TransferProgress::new(0),
+            cancel: download.cancel_token(),
+        },
+    );
+    ctx.request_repaint();
+
+    tokio::spawn({
+        let shared_state = shared_state.clone();
+        let ctx = ctx.clone();
+        async move {
+            while let Ok(ev) = recv.recv_async().await {
+                let mut state = shared_state.lock().unwrap();
+                let Some(job) = state.jobs.get_mut(&id) else {
+                    continue;
+                };
+                job.progress.on_get(ev);
+                ctx.request_repaint();
+            }
+        }
+    });
+
+    let res = download.finish().await;
+    let mut state = shared_state.lock().unwrap();
+    state.jobs.remove(&id);
+    match res {
+        Ok(stats) => {
+            info!(
+                "downloaded {} files, {} bytes in {:?}",
+                stats.files.len(),
+                stats.bytes_read,
+                stats.elapsed
+            );
+        }
+        Err(SendmeError::Cancelled) => {
+            info!("download cancelled");
+        }
+        Err(err) => {
+            error!("failed: {:?}", err);
+            state.errors.push(anyhow::Error::new(err).context("get"));
+        }
+    }
+    ctx.request_repaint();
+}
+
+/// Listen for offers, announce the inbox on the local network and keep the
+/// list of nearby devices up to date.
+async fn inbox_job(shared_state: Arc<Mutex<SharedState>>, ctx: egui::Context) {
+    let (relay, name, hidden) = {
+        let state = shared_state.lock().unwrap();
+        (state.relay.clone(), state.device_name.clone(), state.hidden)
+    };
+    let (offers_send, offers_recv) = flume::unbounded();
+    let inbox = match sendme::Inbox::start(relay.clone(), offers_send).await {
+        Ok(inbox) => inbox,
+        Err(err) => {
+            warn!("inbox is not available: {:?}", err);
+            return;
+        }
+    };
+
+    let (nearby_send, nearby_recv) = flume::bounded(4);
+    let discovery = sendme::Discovery::start(name, !hidden, &inbox, nearby_send)
+        .map_err(|err| warn!("local discovery is not available: {:?}", err))
+        .ok();
+    tokio::spawn({
+        let shared_state = shared_state.clone();
+        let ctx = ctx.clone();
+        async move {
+            while let Ok(nearby) = nearby_recv.recv_async().await {
+                shared_state.lock().unwrap().nearby = nearby;
+                ctx.request_repaint();
+            }
+        }
+    });
+
+    let ticket = inbox
+        .ticket(&relay)
+        .await
+        .map_err(|err| warn!("failed to make inbox ticket: {:?}", err))
+        .ok();
+    {
+        let mut state = shared_state.lock().unwrap();
+        state.inbox = Some(inbox);
+        state.inbox_ticket = ticket;
+        state.discovery = discovery;
+    }
+    ctx.request_repaint();
+
+    while let Ok(offer) = offers_recv.recv_async().await {
+        info!("offer from {}: {}", offer.from, offer.ticket);
+        shared_state.lock().unwrap().offers.push(offer);
+        ctx.request_repaint();
+    }
+}
+
+/// Record the provider events of the share with the given `id` in the shared state.
+async fn track_share_events(
+    id: JobId,
+    events: flume::Receiver<Event>,
+    shared_state: Arc<Mutex<SharedState>>,
+    ctx: egui::Context,
+) {
+    while let Ok(event) = events.recv_async().await {
+        let mut state = shared_state.lock().unwrap();
+        let Some(share) = state.shares.iter_mut().find(|share| share.id == id) else {
+            continue;
+        };
+        match event {
+            Event::ClientConnected { connection_id, .. } => {
+                share.peers.entry(connection_id).or_default();
+            }
+            Event::GetRequestReceived { connection_id, .. } => {
+                let peer = share.peers.entry(connection_id).or_default();
+                peer.requests += 1;
+                peer.status = PeerStatus::Transferring;
+            }
+            Event::TransferHashSeqStarted {
+                connection_id,
+                num_blobs,
+                ..
+            } => {
+                share.peers.entry(connection_id).or_default().blobs_total = Some(num_blobs);
+            }
+            Event::TransferBlobCompleted {
+                connection_id,
+                size,
+                ..
+            } => {
+                let peer = share.peers.entry(connection_id).or_default();
+                peer.blobs_sent += 1;
+                peer.bytes_sent += size;
+            }
+            Event::TransferCompleted { connection_id, .. } => {
+                share.peers.entry(connection_id).or_default().status = PeerStatus::Done;
+            }
+            Event::TransferAborted { connection_id, .. } => {
+                share.peers.entry(connection_id).or_default().status = PeerStatus::Aborted;
+            }
+            _ => continue,
+        }
+        ctx.request_repaint();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh_bytes::{BlobFormat, Hash};
+    use iroh_net::key::SecretKey;
+
+    use super::*;
+
+    fn preview(selected: Vec<bool>) -> TicketPreview {
+        let addr = NodeAddr::new(SecretKey::generate().public());
+        let ticket = BlobTicket::new(addr, Hash::new(b"collection"), BlobFormat::HashSeq).unwrap();
+        TicketPreview {
+            id: 0,
+            ticket,
+            files: None,
+            selected,
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    #[test]
+    fn selection_of_a_preview() {
+        assert_eq!(preview(vec![true, true, true]).selection(), None);
+        assert_eq!(preview(vec![]).selection(), None);
+        let selection = preview(vec![false, true, false, true]).selection();
+        assert_eq!(selection, Some(vec![1, 3]));
+        assert_eq!(preview(vec![false, false]).selection(), Some(vec![]));
+    }
+}